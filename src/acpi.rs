@@ -1,14 +1,410 @@
-pub(crate) fn read_temps(temps: &mut [Option<Temp>]) -> Result<(), crate::Error> {
-	for (i, out) in temps.iter_mut().enumerate() {
-		let path = HWMON_PATH.join(format!("temp{}_input", i + 1));
-		match read_line(&path) {
-			Ok(temp) => *out = Some(Temp(ordered_float::NotNan::from(temp) / 1000.)),
-			Err(crate::Error::Enxio) => *out = None,
-			Err(err) => return Err(err),
+/// The hardware monitoring sysfs interface provided by the thinkpad-acpi kernel module
+#[derive(Clone, Debug)]
+pub(crate) struct Hwmon {
+	/// Path to the root of the hwmon device
+	path: std::path::PathBuf,
+
+	/// The fans, starting from the one with `fan1_input`. There is always at least one.
+	fans: Vec<Fan>,
+
+	/// Path of the fan watchdog file
+	fan_watchdog_path: std::path::PathBuf,
+}
+
+/// The files of one fan of the hwmon device
+#[derive(Clone, Debug)]
+struct Fan {
+	/// Path of the file with the fan speed
+	input_path: std::path::PathBuf,
+
+	/// Path of the file with the pwm mode.
+	///
+	/// Fans that do not have their own `pwm*_enable` file are controlled by `pwm1_enable` together with the first fan.
+	/// This is the case for the second fan of dual-fan Thinkpads with most kernels.
+	pwm_enable_path: std::path::PathBuf,
+
+	/// Path of the file with the fan level
+	pwm_path: std::path::PathBuf,
+}
+
+impl Hwmon {
+	/// The directory that the kernel exposes hwmon devices under
+	pub(crate) const DEFAULT_ROOT: &'static str = "/sys/class/hwmon";
+
+	/// Finds the hwmon device of the thinkpad-acpi kernel module among the hwmon devices in the given directory.
+	///
+	/// The directory can also be the hwmon device itself.
+	pub(crate) fn find(root: &std::path::Path) -> Result<Self, crate::Error> {
+		if is_thinkpad_hwmon(root) {
+			return Ok(Hwmon::new(root.to_owned()));
+		}
+
+		let dir_entries = std::fs::read_dir(root).map_err(|err| crate::Error::Acpi(root.to_owned(), err))?;
+		for dir_entry in dir_entries.flatten() {
+			let dir_path = dir_entry.path();
+			if is_thinkpad_hwmon(&dir_path) {
+				return Ok(Hwmon::new(dir_path));
+			}
+		}
+
+		Err(crate::Error::HwmonNotFound(root.to_owned()))
+	}
+
+	fn new(path: std::path::PathBuf) -> Self {
+		let mut fans = vec![];
+		for index in 1.. {
+			let input_path = path.join(format!("fan{index}_input"));
+			// The first fan is always used, so that a missing `fan1_input` is reported when it's read.
+			if index > 1 && !input_path.exists() {
+				break;
+			}
+
+			let pwm_index = if path.join(format!("pwm{index}_enable")).exists() { index } else { 1 };
+			fans.push(Fan {
+				input_path,
+				pwm_enable_path: path.join(format!("pwm{pwm_index}_enable")),
+				pwm_path: path.join(format!("pwm{pwm_index}")),
+			});
+		}
+
+		Hwmon {
+			fans,
+			fan_watchdog_path: path.join("device").join("driver").join("fan_watchdog"),
+			path,
+		}
+	}
+
+	/// Checks whether the hwmon device has the pwm files to control the fans.
+	pub(crate) fn has_pwm(&self) -> bool {
+		self.fans.iter().any(|fan| fan.pwm_enable_path.exists())
+	}
+
+	/// Checks whether the temperature sensor with the given index, starting from 1, exists.
+	pub(crate) fn has_temp_sensor(&self, index: usize) -> bool {
+		self.path.join(format!("temp{index}_input")).exists()
+	}
+
+	pub(crate) fn read_temps(&self, temps: &mut [Option<Temp>]) -> Result<(), crate::Error> {
+		for (i, out) in temps.iter_mut().enumerate() {
+			let path = self.path.join(format!("temp{}_input", i + 1));
+			match read_line(&path) {
+				Ok(temp) => *out = Some(Temp(ordered_float::NotNan::from(temp) / 1000.)),
+				Err(crate::Error::Enxio) => *out = None,
+				Err(err) => return Err(err),
+			}
 		}
+
+		Ok(())
+	}
+
+	/// The fan with the given index, starting from 1.
+	fn fan(&self, index: usize) -> Result<&Fan, crate::Error> {
+		index.checked_sub(1).and_then(|i| self.fans.get(i)).ok_or(crate::Error::FanNotFound(index))
+	}
+}
+
+/// A way of reading and controlling the fans of the thinkpad-acpi kernel module
+pub(crate) trait FanBackend: std::fmt::Debug {
+	/// Describes the backend for logs, such as with the path of its files
+	fn description(&self) -> String;
+
+	/// The number of fans. There is always at least one.
+	fn num_fans(&self) -> usize;
+
+	/// The indices of the fans, starting from 1, that can be set to a level independently.
+	///
+	/// The other fans are controlled together with the first fan, so they follow whatever level it's set to.
+	fn controllable_fans(&self) -> Vec<usize>;
+
+	/// Reads the level and speed of every fan.
+	fn read_fans(&self) -> Result<Vec<(FanLevel, FanSpeed)>, crate::Error> {
+		(1..=self.num_fans()).map(|index| self.read_fan(index)).collect()
 	}
 
-	Ok(())
+	/// Reads the level and speed of the fan with the given index, starting from 1.
+	fn read_fan(&self, index: usize) -> Result<(FanLevel, FanSpeed), crate::Error>;
+
+	/// Checks whether the fans can be controlled by this process.
+	fn fan_is_writable(&self) -> Result<bool, crate::Error>;
+
+	/// Arms the fan watchdog, so that the fans are set back to automatic control by the BIOS
+	/// if they are not written to again within the given timeout.
+	fn arm_watchdog(&self, timeout: std::time::Duration) -> Result<(), crate::Error>;
+
+	/// Sets the level of the fan with the given index, starting from 1. This also sets the fans that are controlled together with it.
+	fn write_fan(&self, index: usize, fan_level: FanLevel) -> Result<(), crate::Error>;
+}
+
+impl FanBackend for Hwmon {
+	fn description(&self) -> String {
+		format!("hwmon device {}", self.path.display())
+	}
+
+	fn num_fans(&self) -> usize {
+		self.fans.len()
+	}
+
+	/// Fans that do not have their own pwm files are controlled together with the first fan.
+	fn controllable_fans(&self) -> Vec<usize> {
+		self.fans.iter().enumerate()
+		.filter(|&(i, fan)| !self.fans[..i].iter().any(|other| other.pwm_enable_path == fan.pwm_enable_path))
+		.map(|(i, _)| i + 1)
+		.collect()
+	}
+
+	fn read_fan(&self, index: usize) -> Result<(FanLevel, FanSpeed), crate::Error> {
+		let fan = self.fan(index)?;
+
+		let pwm_mode = read_line(&fan.pwm_enable_path)?;
+		let level = match pwm_mode {
+			2 => FanLevel::Auto,
+
+			1 => {
+				let hwmon_level = read_line(&fan.pwm_path)?;
+				FanLevel::Firmware(
+					FanFirmwareLevel::from_hwmon_level(hwmon_level)
+					.ok_or_else(|| crate::Error::Acpi(
+						fan.pwm_enable_path.clone(),
+						std::io::Error::new(std::io::ErrorKind::Other, format!("unrecognized hwmon level {hwmon_level}")),
+					))?)
+			},
+
+			0 => FanLevel::Disengaged,
+
+			level => return Err(crate::Error::Acpi(
+				fan.pwm_enable_path.clone(),
+				std::io::Error::new(std::io::ErrorKind::Other, format!("unrecognized PWM mode {level}")),
+			)),
+		};
+
+		let speed = FanSpeed(read_line(&fan.input_path)?);
+
+		Ok((level, speed))
+	}
+
+	/// Checks whether the pwm mode and fan watchdog files are writable.
+	fn fan_is_writable(&self) -> Result<bool, crate::Error> {
+		for path in self.fans.iter().map(|fan| &fan.pwm_enable_path).chain([&self.fan_watchdog_path]) {
+			match std::fs::OpenOptions::new().write(true).open(path) {
+				Ok(_) => (),
+				Err(ref err) if err.kind() == std::io::ErrorKind::PermissionDenied => return Ok(false),
+				Err(err) => return Err(crate::Error::Acpi(path.clone(), err)),
+			}
+		}
+
+		Ok(true)
+	}
+
+	fn arm_watchdog(&self, timeout: std::time::Duration) -> Result<(), crate::Error> {
+		use std::io::Write;
+
+		let mut file = std::fs::File::create(&self.fan_watchdog_path).map_err(|err| crate::Error::Acpi(
+			self.fan_watchdog_path.clone(),
+			err,
+		))?;
+
+		write!(file, "{}", timeout.as_secs()).map_err(|err| crate::Error::Acpi(
+			self.fan_watchdog_path.clone(),
+			err,
+		))?;
+
+		Ok(())
+	}
+
+	fn write_fan(&self, index: usize, fan_level: FanLevel) -> Result<(), crate::Error> {
+		use std::io::Write;
+
+		let fan = self.fan(index)?;
+
+		match fan_level {
+			FanLevel::Auto => {
+				let mut file = std::fs::File::create(&fan.pwm_enable_path).map_err(|err| crate::Error::Acpi(
+					fan.pwm_enable_path.clone(),
+					err,
+				))?;
+
+				write!(file, "2").map_err(|err| crate::Error::Acpi(
+					fan.pwm_enable_path.clone(),
+					err,
+				))?;
+			},
+
+			FanLevel::Firmware(fan_firmware_level) => {
+				{
+					let mut file = std::fs::File::create(&fan.pwm_enable_path).map_err(|err| crate::Error::Acpi(
+						fan.pwm_enable_path.clone(),
+						err,
+					))?;
+
+					write!(file, "1").map_err(|err| crate::Error::Acpi(
+						fan.pwm_enable_path.clone(),
+						err,
+					))?;
+				}
+
+				{
+					let mut file = std::fs::File::create(&fan.pwm_path).map_err(|err| crate::Error::Acpi(
+						fan.pwm_path.clone(),
+						err,
+					))?;
+
+					write!(file, "{}", fan_firmware_level.to_hwmon_level()).map_err(|err| crate::Error::Acpi(
+						fan.pwm_path.clone(),
+						err,
+					))?;
+				}
+			},
+
+			FanLevel::Disengaged => {
+				let mut file = std::fs::File::create(&fan.pwm_enable_path).map_err(|err| crate::Error::Acpi(
+					fan.pwm_enable_path.clone(),
+					err,
+				))?;
+
+				write!(file, "0").map_err(|err| crate::Error::Acpi(
+					fan.pwm_enable_path.clone(),
+					err,
+				))?;
+			},
+		}
+
+		Ok(())
+	}
+}
+
+/// The fan file in procfs provided by the thinkpad-acpi kernel module.
+///
+/// It reports and sets the level of the first fan, and accepts commands like `level auto` and `watchdog 10`.
+/// It can be used instead of the hwmon device if its pwm files are missing or do not work.
+#[derive(Debug)]
+pub(crate) struct ProcFan {
+	path: std::path::PathBuf,
+}
+
+impl ProcFan {
+	/// The file that the kernel exposes the fan as
+	pub(crate) const DEFAULT_PATH: &'static str = "/proc/acpi/ibm/fan";
+
+	pub(crate) fn new(path: std::path::PathBuf) -> Self {
+		ProcFan { path }
+	}
+
+	pub(crate) fn exists(&self) -> bool {
+		self.path.exists()
+	}
+
+	/// Writes one command to the file.
+	fn write_command(&self, command: &str) -> Result<(), crate::Error> {
+		use std::io::Write;
+
+		let mut file = std::fs::OpenOptions::new().write(true).truncate(true).open(&self.path).map_err(|err| crate::Error::Acpi(
+			self.path.clone(),
+			err,
+		))?;
+
+		write!(file, "{command}").map_err(|err| crate::Error::Acpi(
+			self.path.clone(),
+			err,
+		))?;
+
+		Ok(())
+	}
+}
+
+impl FanBackend for ProcFan {
+	fn description(&self) -> String {
+		self.path.display().to_string()
+	}
+
+	fn num_fans(&self) -> usize {
+		1
+	}
+
+	fn controllable_fans(&self) -> Vec<usize> {
+		vec![1]
+	}
+
+	/// Parses the `level:` and `speed:` lines of the file, such as:
+	///
+	/// ```text
+	/// status:     enabled
+	/// speed:      2900
+	/// level:      auto
+	/// ```
+	fn read_fan(&self, index: usize) -> Result<(FanLevel, FanSpeed), crate::Error> {
+		if index != 1 {
+			return Err(crate::Error::FanNotFound(index));
+		}
+
+		let err = |message: String| crate::Error::Acpi(self.path.clone(), std::io::Error::new(std::io::ErrorKind::Other, message));
+
+		let contents = std::fs::read_to_string(&self.path).map_err(|err| crate::Error::Acpi(
+			self.path.clone(),
+			err,
+		))?;
+
+		let mut level = None;
+		let mut speed = None;
+
+		for line in contents.lines() {
+			match line.split_once(':') {
+				Some(("level", value)) => level = Some(match value.trim() {
+					"auto" => FanLevel::Auto,
+					value =>
+						value.parse::<crate::model::DesiredManualFanLevel>()
+						.map(Into::into)
+						.map_err(|()| err(format!("unrecognized level {value}")))?,
+				}),
+
+				Some(("speed", value)) => speed = Some(FanSpeed(value.trim().parse().map_err(|_| err(format!("unrecognized speed {}", value.trim())))?)),
+
+				_ => (),
+			}
+		}
+
+		let level = level.ok_or_else(|| err("missing level".to_owned()))?;
+		let speed = speed.ok_or_else(|| err("missing speed".to_owned()))?;
+		Ok((level, speed))
+	}
+
+	fn fan_is_writable(&self) -> Result<bool, crate::Error> {
+		match std::fs::OpenOptions::new().write(true).open(&self.path) {
+			Ok(_) => Ok(true),
+			Err(ref err) if err.kind() == std::io::ErrorKind::PermissionDenied => Ok(false),
+			Err(err) => Err(crate::Error::Acpi(self.path.clone(), err)),
+		}
+	}
+
+	fn arm_watchdog(&self, timeout: std::time::Duration) -> Result<(), crate::Error> {
+		self.write_command(&format!("watchdog {}", timeout.as_secs()))
+	}
+
+	fn write_fan(&self, index: usize, fan_level: FanLevel) -> Result<(), crate::Error> {
+		if index != 1 {
+			return Err(crate::Error::FanNotFound(index));
+		}
+
+		match fan_level {
+			FanLevel::Auto => self.write_command("level auto"),
+			FanLevel::Firmware(fan_firmware_level) => self.write_command(&format!("level {fan_firmware_level}")),
+			FanLevel::Disengaged => self.write_command("level disengaged"),
+		}
+	}
+}
+
+/// Sets the fans back to automatic control by the BIOS when dropped, such as when the process exits or panics.
+#[derive(Debug)]
+pub(crate) struct RestoreAutoOnDrop(pub(crate) std::rc::Rc<dyn FanBackend>);
+
+impl Drop for RestoreAutoOnDrop {
+	fn drop(&mut self) {
+		for index in self.0.controllable_fans() {
+			match self.0.write_fan(index, FanLevel::Auto) {
+				Ok(()) => log::info!("restored BIOS control of fan {index}"),
+				Err(err) => log::error!("could not restore BIOS control of fan {index}: {err:?}"),
+			}
+		}
+	}
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -61,119 +457,17 @@ impl std::fmt::Display for TempDisplay {
 	}
 }
 
-pub(crate) fn read_fan() -> Result<(FanLevel, FanSpeed), crate::Error> {
-	let pwm_mode = read_line(&PWM_ENABLE_PATH)?;
-	let level = match pwm_mode {
-		2 => FanLevel::Auto,
-
-		1 => {
-			let hwmon_level = read_line(&PWM_PATH)?;
-			FanLevel::Firmware(
-				FanFirmwareLevel::from_hwmon_level(hwmon_level)
-				.ok_or_else(|| crate::Error::Acpi(
-					PWM_ENABLE_PATH.clone(),
-					std::io::Error::new(std::io::ErrorKind::Other, format!("unrecognized hwmon level {hwmon_level}")),
-				))?)
-		},
-
-		0 => FanLevel::FullSpeed,
-
-		level => return Err(crate::Error::Acpi(
-			PWM_ENABLE_PATH.clone(),
-			std::io::Error::new(std::io::ErrorKind::Other, format!("unrecognized PWM mode {level}")),
-		)),
-	};
-
-	let speed = FanSpeed(read_line(&FAN_INPUT_PATH)?);
-
-	Ok((level, speed))
-}
-
-pub(crate) fn fan_is_writable(update_interval: std::time::Duration) -> Result<bool, crate::Error> {
-	use std::io::Write;
-
-	match std::fs::File::create(&*FAN_WATCHDOG_PATH) {
-		Ok(mut file) => {
-			write!(&mut file, "{}", update_interval.as_secs() * 2).map_err(|err| crate::Error::Acpi(
-				FAN_WATCHDOG_PATH.clone(),
-				err,
-			))?;
-
-			Ok(true)
-		},
-
-		Err(ref err) if err.kind() == std::io::ErrorKind::PermissionDenied => Ok(false),
-
-		Err(err) => Err(crate::Error::Acpi(
-			FAN_WATCHDOG_PATH.clone(),
-			err,
-		)),
-	}
-}
-
-pub(crate) fn write_fan(fan_level: FanLevel) -> Result<(), crate::Error> {
-	use std::io::Write;
-
-	match fan_level {
-		FanLevel::Auto => {
-			let mut file = std::fs::File::create(&*PWM_ENABLE_PATH).map_err(|err| crate::Error::Acpi(
-				PWM_ENABLE_PATH.clone(),
-				err,
-			))?;
-
-			write!(file, "2").map_err(|err| crate::Error::Acpi(
-				PWM_ENABLE_PATH.clone(),
-				err,
-			))?;
-		},
-
-		FanLevel::Firmware(fan_firmware_level) => {
-			{
-				let mut file = std::fs::File::create(&*PWM_ENABLE_PATH).map_err(|err| crate::Error::Acpi(
-					PWM_ENABLE_PATH.clone(),
-					err,
-				))?;
-
-				write!(file, "1").map_err(|err| crate::Error::Acpi(
-					PWM_ENABLE_PATH.clone(),
-					err,
-				))?;
-			}
-
-			{
-				let mut file = std::fs::File::create(&*PWM_PATH).map_err(|err| crate::Error::Acpi(
-					PWM_PATH.clone(),
-					err,
-				))?;
-
-				write!(file, "{}", fan_firmware_level.to_hwmon_level()).map_err(|err| crate::Error::Acpi(
-					PWM_PATH.clone(),
-					err,
-				))?;
-			}
-		},
-
-		FanLevel::FullSpeed => {
-			let mut file = std::fs::File::create(&*PWM_ENABLE_PATH).map_err(|err| crate::Error::Acpi(
-				PWM_ENABLE_PATH.clone(),
-				err,
-			))?;
-
-			write!(file, "0").map_err(|err| crate::Error::Acpi(
-				PWM_ENABLE_PATH.clone(),
-				err,
-			))?;
-		},
-	}
-
-	Ok(())
-}
-
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub(crate) enum FanLevel {
 	Auto,
+
+	/// A level whose speed is regulated by the EC. [`FanFirmwareLevel::Seven`] is the fastest of these.
 	Firmware(FanFirmwareLevel),
-	FullSpeed,
+
+	/// The EC does not regulate the speed of the fan, and runs it as fast as it can go, which is faster than level 7.
+	///
+	/// The thinkpad-acpi kernel module calls this both "disengaged" and "full-speed". It's `pwm1_enable=0` in hwmon.
+	Disengaged,
 }
 
 impl std::fmt::Display for FanLevel {
@@ -181,7 +475,7 @@ impl std::fmt::Display for FanLevel {
 		match self {
 			FanLevel::Auto => write!(f, "Auto"),
 			FanLevel::Firmware(level) => write!(f, "{level}"),
-			FanLevel::FullSpeed => write!(f, "Full speed"),
+			FanLevel::Disengaged => write!(f, "Disengaged"),
 		}
 	}
 }
@@ -195,7 +489,7 @@ impl std::fmt::Display for FanSpeed {
 	}
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub(crate) enum FanFirmwareLevel {
 	Zero = 0,
 	One = 36,
@@ -208,6 +502,17 @@ pub(crate) enum FanFirmwareLevel {
 }
 
 impl FanFirmwareLevel {
+	pub(crate) const ALL: [FanFirmwareLevel; 8] = [
+		FanFirmwareLevel::Zero,
+		FanFirmwareLevel::One,
+		FanFirmwareLevel::Two,
+		FanFirmwareLevel::Three,
+		FanFirmwareLevel::Four,
+		FanFirmwareLevel::Five,
+		FanFirmwareLevel::Six,
+		FanFirmwareLevel::Seven,
+	];
+
 	fn from_hwmon_level(hwmon_level: u32) -> Option<FanFirmwareLevel> {
 		match hwmon_level {
 			hwmon_level if hwmon_level == FanFirmwareLevel::Zero as u32 => Some(FanFirmwareLevel::Zero),
@@ -222,9 +527,19 @@ impl FanFirmwareLevel {
 		}
 	}
 
-	fn to_hwmon_level(self) -> u32 {
+	/// The value of `pwm1` for this level, ie the PWM duty cycle of the fan out of 255
+	pub(crate) fn to_hwmon_level(self) -> u32 {
 		self as u32
 	}
+
+	/// The level whose PWM duty cycle is nearest to the given one.
+	///
+	/// The thinkpad-acpi kernel module only supports these eight levels, and would otherwise round the duty cycle down to one of them.
+	pub(crate) fn nearest(duty: f64) -> Self {
+		FanFirmwareLevel::ALL.into_iter()
+		.min_by_key(|level| ordered_float::NotNan::new((f64::from(level.to_hwmon_level()) - duty).abs()).unwrap_or_default())
+		.expect("array is not empty")
+	}
 }
 
 impl std::fmt::Display for FanFirmwareLevel {
@@ -242,32 +557,16 @@ impl std::fmt::Display for FanFirmwareLevel {
 	}
 }
 
-/// Path to the root of the hardware monitoring sysfs interface provided by the thinkpad-acpi kernel module
-static HWMON_PATH: once_cell::sync::Lazy<std::path::PathBuf> = once_cell::sync::Lazy::new(|| {
-	for dir_entry in std::fs::read_dir("/sys/class/hwmon").unwrap().flatten() {
-		let dir_path = dir_entry.path();
-		if let Ok(mut name_file) = std::fs::File::open(dir_path.join("name")) {
-			let mut name = String::new();
-			if std::io::Read::read_to_string(&mut name_file, &mut name).is_ok() && name == "thinkpad\n" {
-				return dir_path;
-			}
+fn is_thinkpad_hwmon(path: &std::path::Path) -> bool {
+	if let Ok(mut name_file) = std::fs::File::open(path.join("name")) {
+		let mut name = String::new();
+		if std::io::Read::read_to_string(&mut name_file, &mut name).is_ok() && name == "thinkpad\n" {
+			return true;
 		}
 	}
 
-	panic!("could not find hwmon device for thinkpad_acpi");
-});
-
-/// Path of the file with the fan speed
-static FAN_INPUT_PATH: once_cell::sync::Lazy<std::path::PathBuf> = once_cell::sync::Lazy::new(|| HWMON_PATH.join("fan1_input"));
-
-/// Path of the fan watchdog file
-static FAN_WATCHDOG_PATH: once_cell::sync::Lazy<std::path::PathBuf> = once_cell::sync::Lazy::new(|| HWMON_PATH.join("device").join("driver").join("fan_watchdog"));
-
-/// Path of the file with the pwm mode
-static PWM_ENABLE_PATH: once_cell::sync::Lazy<std::path::PathBuf> = once_cell::sync::Lazy::new(|| HWMON_PATH.join("pwm1_enable"));
-
-/// Path of the file with the fan level
-static PWM_PATH: once_cell::sync::Lazy<std::path::PathBuf> = once_cell::sync::Lazy::new(|| HWMON_PATH.join("pwm1"));
+	false
+}
 
 fn read_line(path: &std::path::Path) -> Result<u32, crate::Error> {
 	let file = std::io::BufReader::new(std::fs::File::open(path).map_err(|err| crate::Error::Acpi(
@@ -294,3 +593,146 @@ fn read_line(path: &std::path::Path) -> Result<u32, crate::Error> {
 		)),
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{FanBackend, FanFirmwareLevel, FanLevel, Hwmon};
+
+	/// A fake `/sys/class/hwmon` in a temporary directory, with one thinkpad-acpi device and one unrelated device
+	struct FakeHwmonRoot(std::path::PathBuf);
+
+	impl FakeHwmonRoot {
+		fn new(name: &str) -> Self {
+			let root = std::env::temp_dir().join(format!("tpfancontrol-test-{}-{name}", std::process::id()));
+			drop(std::fs::remove_dir_all(&root));
+
+			let result = FakeHwmonRoot(root);
+			result.write("hwmon0/name", "acpitz\n");
+			result.write("hwmon0/temp1_input", "99000\n");
+			result.write("hwmon1/name", "thinkpad\n");
+			result.write("hwmon1/temp1_input", "45000\n");
+			result.write("hwmon1/temp2_input", "51500\n");
+			result.write("hwmon1/pwm1_enable", "1\n");
+			result.write("hwmon1/pwm1", "109\n");
+			result.write("hwmon1/fan1_input", "2500\n");
+			result.write("hwmon1/device/driver/fan_watchdog", "0\n");
+			result
+		}
+
+		fn write(&self, path: &str, contents: &str) {
+			let path = self.0.join(path);
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(path, contents).unwrap();
+		}
+
+		fn read(&self, path: &str) -> String {
+			std::fs::read_to_string(self.0.join(path)).unwrap()
+		}
+	}
+
+	impl Drop for FakeHwmonRoot {
+		fn drop(&mut self) {
+			drop(std::fs::remove_dir_all(&self.0));
+		}
+	}
+
+	#[test]
+	fn find() {
+		let root = FakeHwmonRoot::new("find");
+
+		let hwmon = Hwmon::find(&root.0).unwrap();
+		assert_eq!(hwmon.path, root.0.join("hwmon1"));
+
+		let hwmon = Hwmon::find(&root.0.join("hwmon1")).unwrap();
+		assert_eq!(hwmon.path, root.0.join("hwmon1"));
+
+		assert!(matches!(Hwmon::find(&root.0.join("hwmon0")), Err(crate::Error::HwmonNotFound(_))));
+	}
+
+	#[test]
+	fn read() {
+		let root = FakeHwmonRoot::new("read");
+		let hwmon = Hwmon::find(&root.0).unwrap();
+
+		let mut temps = vec![None; 2];
+		hwmon.read_temps(&mut temps).unwrap();
+		assert_eq!(temps, [Some(super::Temp(45.0.try_into().unwrap())), Some(super::Temp(51.5.try_into().unwrap()))]);
+
+		let mut temps = vec![None; 3];
+		assert!(matches!(hwmon.read_temps(&mut temps), Err(crate::Error::Acpi(..))));
+
+		let (fan_level, fan_speed) = hwmon.read_fan(1).unwrap();
+		assert_eq!(fan_level, FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(fan_speed.0, 2500);
+		assert_eq!(hwmon.read_fans().unwrap().len(), 1);
+	}
+
+	#[test]
+	fn dual_fans() {
+		let root = FakeHwmonRoot::new("dual_fans");
+		root.write("hwmon1/fan2_input", "2700\n");
+
+		// The second fan is controlled together with the first one.
+		let hwmon = Hwmon::find(&root.0).unwrap();
+		assert_eq!(hwmon.num_fans(), 2);
+		assert_eq!(hwmon.controllable_fans(), [1]);
+		let fans = hwmon.read_fans().unwrap();
+		assert_eq!(fans.iter().map(|(fan_level, fan_speed)| (*fan_level, fan_speed.0)).collect::<Vec<_>>(), [
+			(FanLevel::Firmware(FanFirmwareLevel::Three), 2500),
+			(FanLevel::Firmware(FanFirmwareLevel::Three), 2700),
+		]);
+
+		// The second fan has its own pwm files.
+		root.write("hwmon1/pwm2_enable", "2\n");
+		root.write("hwmon1/pwm2", "255\n");
+		let hwmon = Hwmon::find(&root.0).unwrap();
+		assert_eq!(hwmon.controllable_fans(), [1, 2]);
+		hwmon.write_fan(2, FanLevel::Firmware(FanFirmwareLevel::Five)).unwrap();
+		assert_eq!(hwmon.read_fan(1).unwrap().0, FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(hwmon.read_fan(2).unwrap().0, FanLevel::Firmware(FanFirmwareLevel::Five));
+	}
+
+	#[test]
+	fn write() {
+		let root = FakeHwmonRoot::new("write");
+		let hwmon = Hwmon::find(&root.0).unwrap();
+
+		assert!(hwmon.fan_is_writable().unwrap());
+		assert_eq!(root.read("hwmon1/device/driver/fan_watchdog"), "0\n");
+
+		hwmon.arm_watchdog(std::time::Duration::from_secs(10)).unwrap();
+		assert_eq!(root.read("hwmon1/device/driver/fan_watchdog"), "10");
+
+		for fan_level in [FanLevel::Auto, FanLevel::Firmware(FanFirmwareLevel::Six), FanLevel::Disengaged, FanLevel::Firmware(FanFirmwareLevel::Zero)] {
+			hwmon.write_fan(1, fan_level).unwrap();
+			assert_eq!(hwmon.read_fan(1).unwrap().0, fan_level);
+		}
+	}
+
+	#[test]
+	fn proc_fan() {
+		let root = FakeHwmonRoot::new("proc_fan");
+		root.write("fan", "status:\t\tenabled\nspeed:\t\t2900\nlevel:\t\t3\ncommands:\tlevel <level> (<level> is 0-7, auto, disengaged, full-speed)\n");
+
+		let proc_fan = super::ProcFan::new(root.0.join("fan"));
+		assert!(proc_fan.fan_is_writable().unwrap());
+
+		let (fan_level, fan_speed) = proc_fan.read_fan(1).unwrap();
+		assert_eq!(fan_level, FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(fan_speed.0, 2900);
+
+		root.write("fan", "status:\t\tenabled\nspeed:\t\t6500\nlevel:\t\tdisengaged\n");
+		assert_eq!(proc_fan.read_fan(1).unwrap().0, FanLevel::Disengaged);
+
+		proc_fan.write_fan(1, FanLevel::Firmware(FanFirmwareLevel::Six)).unwrap();
+		assert_eq!(root.read("fan"), "level 6");
+
+		proc_fan.arm_watchdog(std::time::Duration::from_secs(10)).unwrap();
+		assert_eq!(root.read("fan"), "watchdog 10");
+
+		assert!(matches!(proc_fan.read_fan(1), Err(crate::Error::Acpi(..))));
+
+		assert!(matches!(proc_fan.read_fan(2), Err(crate::Error::FanNotFound(2))));
+		assert!(matches!(proc_fan.write_fan(2, FanLevel::Auto), Err(crate::Error::FanNotFound(2))));
+	}
+}
@@ -0,0 +1,572 @@
+/// Checks the config file for errors, and cross-checks it against the hwmon device if one can be found.
+///
+/// Prints each problem that was found, and fails if any of them are errors.
+pub(crate) fn run(args: &crate::cli::Args) -> Result<(), crate::Error> {
+	let path = args.config_path();
+
+	let source = std::fs::read_to_string(&path).map_err(|err| crate::Error::Config(path.clone(), err))?;
+
+	let hwmon = match crate::acpi::Hwmon::find(&args.hwmon_root) {
+		Ok(hwmon) => Some(hwmon),
+		Err(err) => {
+			eprintln!("{}: warning: skipping checks against the hwmon device: {err:?}", path.display());
+			None
+		},
+	};
+
+	let diagnostics = check(&source, hwmon.as_ref(), &args.proc_fan);
+
+	let mut num_errors = 0;
+	for diagnostic in &diagnostics {
+		if let Severity::Error = diagnostic.severity {
+			num_errors += 1;
+		}
+
+		match diagnostic.line {
+			Some(line) => eprintln!("{}:{line}: {diagnostic}", path.display()),
+			None => eprintln!("{}: {diagnostic}", path.display()),
+		}
+	}
+
+	if num_errors > 0 {
+		return Err(crate::Error::ConfigCheckFailed(path, num_errors));
+	}
+
+	println!("{}: OK", path.display());
+
+	Ok(())
+}
+
+#[derive(Debug)]
+struct Diagnostic {
+	severity: Severity,
+
+	/// The line of the config file that the problem is at, starting from 1
+	line: Option<usize>,
+
+	/// The key that the problem is with, such as `fan_level.65`
+	key: Option<String>,
+
+	message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match &self.key {
+			Some(key) => write!(f, "{}: {key}: {}", self.severity, self.message),
+			None => write!(f, "{}: {}", self.severity, self.message),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Severity {
+	Error,
+	Warning,
+}
+
+impl std::fmt::Display for Severity {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Severity::Error => write!(f, "error"),
+			Severity::Warning => write!(f, "warning"),
+		}
+	}
+}
+
+/// `proc_fan` is the path of the fan file in procfs, for when the config chooses the procfs fan backend.
+fn check(source: &str, hwmon: Option<&crate::acpi::Hwmon>, proc_fan: &std::path::Path) -> Vec<Diagnostic> {
+	let mut diagnostics = vec![];
+
+	let value: toml::Value = match toml::from_str(source) {
+		Ok(value) => value,
+		Err(err) => {
+			diagnostics.push(Diagnostic {
+				severity: Severity::Error,
+				line: err.line_col().map(|(line, _)| line + 1),
+				key: None,
+				message: err.to_string(),
+			});
+			return diagnostics;
+		},
+	};
+
+	// This only fails for values that the config never has, like datetimes, which are reported as invalid below.
+	let located: Option<LocatedValue> = toml::from_str(source).ok();
+	let mut lines = Default::default();
+	if let Some(located) = &located {
+		key_lines(source, located, "", &mut lines);
+	}
+	let line = |key: &str| lines.get(key).copied();
+
+	// Misspelled keys can be why the config is invalid, such as a misspelled required key, so they're reported first.
+	if let Some(LocatedValue::Table(table)) = &located {
+		check_unknown_keys(source, table, "", "", &mut diagnostics);
+	}
+
+	let config: crate::model::Config = match toml::from_str(source) {
+		Ok(config) => config,
+		Err(err) => {
+			diagnostics.push(Diagnostic {
+				severity: Severity::Error,
+				line: err.line_col().map(|(line, _)| line + 1),
+				key: None,
+				message: err.to_string(),
+			});
+			return diagnostics;
+		},
+	};
+
+	if let Some(hwmon) = hwmon {
+		let fan = config.fan_backend.open(hwmon, proc_fan);
+
+		for (i, name) in config.sensors.iter().enumerate() {
+			let index = i + 1;
+			if name.is_some() && !hwmon.has_temp_sensor(index) {
+				diagnostics.push(Diagnostic {
+					severity: Severity::Error,
+					line: line(&format!("sensors.{index}")),
+					key: Some(format!("sensors.{index}")),
+					message: format!("unknown sensor index {index}; the hwmon device does not have temp{index}_input"),
+				});
+			}
+		}
+
+		for (i, curve) in config.curves.iter().enumerate() {
+			for &index in &curve.sensors {
+				if !hwmon.has_temp_sensor(index) {
+					diagnostics.push(Diagnostic {
+						severity: Severity::Error,
+						line: line(&format!("curve[{i}].sensors")),
+						key: Some(format!("curve[{i}].sensors")),
+						message: format!("unknown sensor index {index}; the hwmon device does not have temp{index}_input"),
+					});
+				}
+			}
+
+			for &index in &curve.fans {
+				if index > fan.num_fans() {
+					diagnostics.push(Diagnostic {
+						severity: Severity::Error,
+						line: line(&format!("curve[{i}].fans")),
+						key: Some(format!("curve[{i}].fans")),
+						message: format!("unknown fan index {index}; {} has {} fan(s)", fan.description(), fan.num_fans()),
+					});
+				}
+			}
+		}
+
+		if config.fan_control == crate::model::FanControl::Separate && fan.controllable_fans().len() < 2 {
+			diagnostics.push(Diagnostic {
+				severity: Severity::Warning,
+				line: line("fan.fans"),
+				key: Some("fan.fans".to_owned()),
+				message: format!("the fans of {} cannot be controlled separately; they will be controlled together", fan.description()),
+			});
+		}
+
+		if let Some(target) = &config.target {
+			if !hwmon.has_temp_sensor(target.sensor) {
+				diagnostics.push(Diagnostic {
+					severity: Severity::Error,
+					line: line("target.sensor"),
+					key: Some("target.sensor".to_owned()),
+					message: format!("unknown sensor index {}; the hwmon device does not have temp{}_input", target.sensor, target.sensor),
+				});
+			}
+		}
+
+		if let Some(safety) = &config.safety {
+			for (i, thresholds) in safety.sensors.iter().enumerate() {
+				let index = i + 1;
+				if thresholds.is_some() && !hwmon.has_temp_sensor(index) {
+					diagnostics.push(Diagnostic {
+						severity: Severity::Error,
+						line: line(&format!("safety.sensors.{index}")),
+						key: Some(format!("safety.sensors.{index}")),
+						message: format!("unknown sensor index {index}; the hwmon device does not have temp{index}_input"),
+					});
+				}
+			}
+		}
+
+		for (i, filter) in config.filters.iter().enumerate() {
+			let index = i + 1;
+			if filter.is_some() && !hwmon.has_temp_sensor(index) {
+				diagnostics.push(Diagnostic {
+					severity: Severity::Error,
+					line: line(&format!("filter.{index}")),
+					key: Some(format!("filter.{index}")),
+					message: format!("unknown sensor index {index}; the hwmon device does not have temp{index}_input"),
+				});
+			}
+		}
+	}
+
+	if let Some(fan_level) = value.get("fan_level").and_then(toml::Value::as_table) {
+		check_fan_level(&lines, "fan_level", fan_level, &config.fan_level, &mut diagnostics);
+	}
+
+	if let Some(curves) = value.get("curve").and_then(toml::Value::as_array) {
+		for ((i, curve), parsed_curve) in curves.iter().enumerate().zip(&config.curves) {
+			if let Some(fan_level) = curve.get("fan_level").and_then(toml::Value::as_table) {
+				check_fan_level(&lines, &format!("curve[{i}].fan_level"), fan_level, &parsed_curve.fan_level, &mut diagnostics);
+			}
+		}
+	}
+
+	if let Err(err) = config.check_watchdog_timeout() {
+		diagnostics.push(Diagnostic {
+			severity: Severity::Error,
+			line: line("fan.watchdog_timeout").or_else(|| line("fan.update_interval")),
+			key: Some("fan.watchdog_timeout".to_owned()),
+			message: format!("{err:?}"),
+		});
+	}
+
+	diagnostics
+}
+
+/// The keys of each table of the config file, by the name of the table. `*` stands for any key of the parent table.
+///
+/// Tables whose keys are sensor indices or temperatures, like `[sensors]` and `[fan_level]`, are not listed.
+const KNOWN_KEYS: &[(&str, &[&str])] = &[
+	("", &["sensors", "fan_level", "smart", "curve", "fan", "control", "target", "filter", "rate_limit", "safety", "read_errors"]),
+	("smart", &["hysteresis", "interpolate"]),
+	("curve", &["sensors", "fans", "fan_level"]),
+	("fan", &["update_interval", "watchdog_timeout", "fans", "backend", "write_retries", "reassert_interval"]),
+	("control", &["users", "groups"]),
+	("target", &["sensor", "temperature", "kp", "ki", "kd", "min_dwell"]),
+	("filter.*", &["type", "alpha", "window"]),
+	("rate_limit", &["max_step_up", "max_step_down", "min_dwell", "bypass_temperature"]),
+	("safety", &["critical", "release", "action", "sensors"]),
+	("safety.sensors.*", &["critical", "release"]),
+	("read_errors", &["on_error", "on_missing", "retries"]),
+];
+
+/// Reports the keys of the table, and of the tables in it, that the config file does not have. These are ignored when the config is loaded,
+/// so a misspelled key silently falls back to its default.
+///
+/// `path` is the path of the table, like `curve[0]`, and `schema` is its name in [`KNOWN_KEYS`], like `curve`.
+fn check_unknown_keys(
+	source: &str,
+	table: &[(toml::Spanned<String>, LocatedValue)],
+	path: &str,
+	schema: &str,
+	diagnostics: &mut Vec<Diagnostic>,
+) {
+	let known_keys =
+		KNOWN_KEYS.iter()
+		.find(|&&(name, _)| name == schema)
+		.map(|&(_, known_keys)| known_keys);
+
+	for (key, value) in table {
+		let key_path = join_key(path, key.get_ref());
+
+		let key_schema = match known_keys {
+			Some(known_keys) if !known_keys.contains(&&**key.get_ref()) => {
+				diagnostics.push(Diagnostic {
+					severity: Severity::Warning,
+					line: Some(line_of(source, key.start())),
+					key: Some(key_path),
+					message: "unknown key; it is ignored".to_owned(),
+				});
+				continue;
+			},
+			Some(_) => join_key(schema, key.get_ref()),
+			None => join_key(schema, "*"),
+		};
+
+		match value {
+			LocatedValue::Table(table) => check_unknown_keys(source, table, &key_path, &key_schema, diagnostics),
+			LocatedValue::Array(values) =>
+				for (i, value) in values.iter().enumerate() {
+					if let LocatedValue::Table(table) = value {
+						check_unknown_keys(source, table, &format!("{key_path}[{i}]"), &key_schema, diagnostics);
+					}
+				},
+			LocatedValue::Other => (),
+		}
+	}
+}
+
+/// Checks a `fan_level` table, given both its raw TOML and its parsed steps.
+///
+/// `table` is the path of the table, like `curve[0].fan_level`.
+fn check_fan_level(
+	lines: &std::collections::BTreeMap<String, usize>,
+	table: &str,
+	fan_level: &toml::value::Table,
+	steps: &[crate::model::FanLevelStep],
+	diagnostics: &mut Vec<Diagnostic>,
+) {
+	// Keys like `65` and `65.0` are different keys to TOML, but the same temperature.
+	let mut keys: std::collections::BTreeMap<crate::acpi::Temp, &str> = Default::default();
+	for key in fan_level.keys() {
+		let temp = match key.parse::<f64>().ok().and_then(|temp| ordered_float::NotNan::new(temp).ok()) {
+			Some(temp) => crate::acpi::Temp(temp),
+			None => continue,
+		};
+
+		if let Some(previous_key) = keys.insert(temp, key) {
+			diagnostics.push(Diagnostic {
+				severity: Severity::Error,
+				line: lines.get(&format!("{table}.{key}")).copied(),
+				key: Some(format!("{table}.{key}")),
+				message: format!("duplicate temperature {} \u{B0}C; it is also set by {table}.{previous_key}", temp.0),
+			});
+		}
+	}
+
+	match steps.first() {
+		Some(step) if *step.lower_bound.0 <= 0. => (),
+		_ => diagnostics.push(Diagnostic {
+			severity: Severity::Warning,
+			line: lines.get(table).copied(),
+			key: Some(table.to_owned()),
+			message: "no entry at 0 \u{B0}C; the fan will be disengaged when the temperature is not above any of the entries".to_owned(),
+		}),
+	}
+
+	for window in steps.windows(2) {
+		// Duplicate temperatures have already been reported above.
+		if window[1].lower_bound != window[0].lower_bound && window[1].level < window[0].level {
+			let key = keys.get(&window[1].lower_bound).copied().unwrap_or_default();
+			diagnostics.push(Diagnostic {
+				severity: Severity::Warning,
+				line: lines.get(&format!("{table}.{key}")).copied(),
+				key: Some(format!("{table}.{key}")),
+				message: format!("fan level {} is lower than the fan level {} of the entry below it", window[1].level, window[0].level),
+			});
+		}
+	}
+}
+
+/// The config file parsed only as far as needed to know where each of its keys is
+#[derive(Debug)]
+enum LocatedValue {
+	Table(Vec<(toml::Spanned<String>, LocatedValue)>),
+	Array(Vec<LocatedValue>),
+	Other,
+}
+
+impl<'de> serde::Deserialize<'de> for LocatedValue {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+		struct Visitor;
+
+		impl<'de> serde::de::Visitor<'de> for Visitor {
+			type Value = LocatedValue;
+
+			fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				write!(f, "a TOML value")
+			}
+
+			fn visit_bool<E>(self, _: bool) -> Result<Self::Value, E> {
+				Ok(LocatedValue::Other)
+			}
+
+			fn visit_i64<E>(self, _: i64) -> Result<Self::Value, E> {
+				Ok(LocatedValue::Other)
+			}
+
+			fn visit_u64<E>(self, _: u64) -> Result<Self::Value, E> {
+				Ok(LocatedValue::Other)
+			}
+
+			fn visit_f64<E>(self, _: f64) -> Result<Self::Value, E> {
+				Ok(LocatedValue::Other)
+			}
+
+			fn visit_str<E>(self, _: &str) -> Result<Self::Value, E> {
+				Ok(LocatedValue::Other)
+			}
+
+			fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where A: serde::de::SeqAccess<'de> {
+				let mut result = vec![];
+				while let Some(value) = serde::de::SeqAccess::next_element(&mut seq)? {
+					result.push(value);
+				}
+				Ok(LocatedValue::Array(result))
+			}
+
+			fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+				let mut result = vec![];
+				while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+					result.push((key, serde::de::MapAccess::next_value(&mut map)?));
+				}
+				Ok(LocatedValue::Table(result))
+			}
+		}
+
+		deserializer.deserialize_any(Visitor)
+	}
+}
+
+/// Collects the line, starting from 1, of every key in the value, by its path like `curve[0].fan_level.50`
+fn key_lines(source: &str, value: &LocatedValue, path: &str, lines: &mut std::collections::BTreeMap<String, usize>) {
+	match value {
+		LocatedValue::Table(table) =>
+			for (key, value) in table {
+				let key_path = join_key(path, key.get_ref());
+				key_lines(source, value, &key_path, lines);
+				// Tables that are continued by later headers, like `[[curve]]`, keep the line where they start.
+				lines.entry(key_path).or_insert_with(|| line_of(source, key.start()));
+			},
+
+		LocatedValue::Array(values) =>
+			for (i, value) in values.iter().enumerate() {
+				key_lines(source, value, &format!("{path}[{i}]"), lines);
+			},
+
+		LocatedValue::Other => (),
+	}
+}
+
+fn join_key(path: &str, key: &str) -> String {
+	if path.is_empty() { key.to_owned() } else { format!("{path}.{key}") }
+}
+
+/// The line, starting from 1, of the given byte offset in the config file
+fn line_of(source: &str, offset: usize) -> usize {
+	source[..offset].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Severity;
+
+	#[test]
+	fn check() {
+		let source = r#"
+[sensors]
+1 = 'cpu'
+
+[fan_level]
+45 = '1'
+'65' = '5'
+'65.0' = '7'
+80 = '3'
+
+[[curve]]
+sensors = [2]
+fan_level = { 0 = '0', 50 = '2', '50.0' = '3' }
+
+[fan]
+update_interval = 5
+watchdog_timeout = 5
+"#;
+
+		let diagnostics: Vec<_> =
+			super::check(source, None, "".as_ref()).into_iter()
+			.map(|diagnostic| (diagnostic.severity, diagnostic.line, diagnostic.key))
+			.collect();
+		assert_eq!(diagnostics, [
+			(Severity::Error, Some(8), Some("fan_level.65.0".to_owned())),
+			(Severity::Warning, Some(5), Some("fan_level".to_owned())),
+			(Severity::Warning, Some(9), Some("fan_level.80".to_owned())),
+			(Severity::Error, Some(13), Some("curve[0].fan_level.50.0".to_owned())),
+			(Severity::Error, Some(17), Some("fan.watchdog_timeout".to_owned())),
+		]);
+	}
+
+	#[test]
+	fn check_unknown_keys() {
+		let source = r#"
+[sensors]
+1 = 'cpu'
+
+[fan_level]
+0 = '0'
+
+[[curve]]
+sensors = [1]
+fan_level = { 0 = '0' }
+
+[[curve]]
+sensor = [2]
+fan_level = { 0 = '0' }
+
+[safety]
+critcal = 95
+
+[safety.sensors]
+1 = { critical = 90, relase = 85 }
+
+[filter.1]
+type = 'median'
+windows = 5
+
+[smrt]
+hysteresis = 2
+"#;
+
+		let diagnostics: Vec<_> =
+			super::check(source, None, "".as_ref()).into_iter()
+			.map(|diagnostic| (diagnostic.severity, diagnostic.line, diagnostic.key))
+			.collect();
+		assert_eq!(diagnostics[..5], [
+			(Severity::Warning, Some(13), Some("curve[1].sensor".to_owned())),
+			(Severity::Warning, Some(17), Some("safety.critcal".to_owned())),
+			(Severity::Warning, Some(20), Some("safety.sensors.1.relase".to_owned())),
+			(Severity::Warning, Some(24), Some("filter.1.windows".to_owned())),
+			(Severity::Warning, Some(26), Some("smrt".to_owned())),
+		]);
+
+		// The median filter is invalid without its window.
+		assert_eq!(diagnostics.len(), 6);
+	}
+
+	#[test]
+	fn check_key_lines() {
+		let source = r#"
+smart.hystersis = 2
+
+[sensors]
+1 = 'cpu'
+
+[fan_level]
+0 = '0'
+
+[control]
+users = [
+	1000, # groups = [0]
+]
+"grou=]ps" = """
+groups = [0]
+"""
+groups = [
+	0,
+]
+
+[fan]
+update_interval = 5
+watchdog_timeout = 5
+"#;
+
+		let diagnostics: Vec<_> =
+			super::check(source, None, "".as_ref()).into_iter()
+			.map(|diagnostic| (diagnostic.severity, diagnostic.line, diagnostic.key))
+			.collect();
+		assert_eq!(diagnostics, [
+			(Severity::Warning, Some(2), Some("smart.hystersis".to_owned())),
+			(Severity::Warning, Some(14), Some("control.grou=]ps".to_owned())),
+			(Severity::Error, Some(23), Some("fan.watchdog_timeout".to_owned())),
+		]);
+	}
+
+	#[test]
+	fn check_sensor_index_zero() {
+		let diagnostics = super::check("[sensors]\n0 = 'cpu'\n\n[fan_level]\n0 = '0'\n", None, "".as_ref());
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].severity, Severity::Error);
+		assert!(diagnostics[0].message.contains("a sensor index"), "{}", diagnostics[0].message);
+	}
+
+	#[test]
+	fn check_syntax_error() {
+		let diagnostics = super::check("[sensors]\n1 = 'cpu'\n[fan_level\n", None, "".as_ref());
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].severity, Severity::Error);
+		assert_eq!(diagnostics[0].line, Some(3));
+	}
+}
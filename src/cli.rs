@@ -0,0 +1,177 @@
+pub(crate) const USAGE: &str = "\
+Usage: tpfancontrol [OPTIONS] [COMMAND]
+
+Commands:
+    (none)                 Run the interactive UI
+    daemon                 Control the fan without a UI, for use as a system service
+    check-config [FILE]    Check the config file for errors. Defaults to the same file as --config
+    status [--json]        Print the temperatures and the state of the fan, as JSON if --json is given
+
+Options:
+    --config <FILE>                  The config file. Defaults to $TPFANCONTROL_CONFIG if set.
+                                     Otherwise $XDG_CONFIG_HOME/tpfancontrol/config.toml if it exists,
+                                     or /etc/tpfancontrol/config.toml if it does not.
+    --hwmon-root <DIR>               Directory to look for the thinkpad_acpi hwmon device in.
+                                     Defaults to $TPFANCONTROL_HWMON_ROOT, or /sys/class/hwmon if that is not set.
+    --proc-fan <FILE>                The fan file of thinkpad_acpi in procfs, for the procfs fan backend.
+                                     Defaults to $TPFANCONTROL_PROC_FAN, or /proc/acpi/ibm/fan if that is not set.
+    --socket <PATH>                  The control socket of the daemon.
+                                     Defaults to $TPFANCONTROL_SOCKET, or /run/tpfancontrol.sock if that is not set.
+    --update-interval <SECONDS>      How often the daemon updates the fan. Overrides the config file.
+    --watchdog-timeout <SECONDS>     Timeout of the fan watchdog, after which the BIOS takes back control of the fan
+                                     if it has not been updated. Overrides the config file.
+    --history <SECONDS>              How far back the UI shows the history of each sensor and of the fan speed.
+                                     Defaults to 150 seconds.";
+
+#[derive(Debug)]
+pub(crate) struct Args {
+	pub(crate) command: Command,
+
+	/// The config file given on the command line or in the environment, if any. See [`Args::config_path`]
+	pub(crate) config: Option<std::path::PathBuf>,
+
+	/// Directory to look for the hwmon device of the thinkpad-acpi kernel module in
+	pub(crate) hwmon_root: std::path::PathBuf,
+
+	/// The fan file of the thinkpad-acpi kernel module in procfs
+	pub(crate) proc_fan: std::path::PathBuf,
+
+	/// The control socket of the daemon
+	pub(crate) socket: std::path::PathBuf,
+
+	pub(crate) update_interval: Option<std::time::Duration>,
+
+	pub(crate) watchdog_timeout: Option<std::time::Duration>,
+
+	/// How far back the UI keeps the history of the readings
+	pub(crate) history: std::time::Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Command {
+	Tui,
+	Daemon,
+	CheckConfig,
+	Status { json: bool },
+}
+
+impl Args {
+	pub(crate) fn parse(mut args: impl Iterator<Item = std::ffi::OsString>) -> Result<Self, crate::Error> {
+		let mut command = None;
+		let mut config = None;
+		let mut hwmon_root = None;
+		let mut proc_fan = None;
+		let mut socket = None;
+		let mut update_interval = None;
+		let mut watchdog_timeout = None;
+		let mut history = None;
+
+		while let Some(arg) = args.next() {
+			if arg == "--config" {
+				config = Some(option_value(&arg, args.next())?.into());
+			}
+			else if arg == "--hwmon-root" {
+				hwmon_root = Some(option_value(&arg, args.next())?.into());
+			}
+			else if arg == "--proc-fan" {
+				proc_fan = Some(option_value(&arg, args.next())?.into());
+			}
+			else if arg == "--socket" {
+				socket = Some(option_value(&arg, args.next())?.into());
+			}
+			else if arg == "--update-interval" {
+				update_interval = Some(seconds_option_value(&arg, args.next())?);
+			}
+			else if arg == "--watchdog-timeout" {
+				watchdog_timeout = Some(seconds_option_value(&arg, args.next())?);
+			}
+			else if arg == "--history" {
+				history = Some(seconds_option_value(&arg, args.next())?);
+			}
+			else if command.is_none() && arg == "daemon" {
+				command = Some(Command::Daemon);
+			}
+			else if command.is_none() && arg == "check-config" {
+				command = Some(Command::CheckConfig);
+			}
+			else if command.is_none() && arg == "status" {
+				command = Some(Command::Status { json: false });
+			}
+			else if matches!(command, Some(Command::Status { .. })) && arg == "--json" {
+				command = Some(Command::Status { json: true });
+			}
+			else if matches!(command, Some(Command::CheckConfig)) && config.is_none() && !arg.to_string_lossy().starts_with('-') {
+				config = Some(arg.into());
+			}
+			else {
+				return Err(crate::Error::Usage(format!("unexpected argument {arg:?}")));
+			}
+		}
+
+		let config = config.or_else(|| std::env::var_os("TPFANCONTROL_CONFIG").map(Into::into));
+
+		let hwmon_root =
+			hwmon_root
+			.or_else(|| std::env::var_os("TPFANCONTROL_HWMON_ROOT").map(Into::into))
+			.unwrap_or_else(|| crate::acpi::Hwmon::DEFAULT_ROOT.into());
+
+		let proc_fan =
+			proc_fan
+			.or_else(|| std::env::var_os("TPFANCONTROL_PROC_FAN").map(Into::into))
+			.unwrap_or_else(|| crate::acpi::ProcFan::DEFAULT_PATH.into());
+
+		let socket =
+			socket
+			.or_else(|| std::env::var_os("TPFANCONTROL_SOCKET").map(Into::into))
+			.unwrap_or_else(|| crate::control::DEFAULT_SOCKET_PATH.into());
+
+		Ok(Args {
+			command: command.unwrap_or(Command::Tui),
+			config,
+			hwmon_root,
+			proc_fan,
+			socket,
+			update_interval,
+			watchdog_timeout,
+			history: history.unwrap_or(std::time::Duration::from_secs(150)),
+		})
+	}
+}
+
+impl Args {
+	/// The path of the config file to load.
+	///
+	/// This is the file given on the command line or in `$TPFANCONTROL_CONFIG` if any.
+	/// Otherwise it's the user's config file in `$XDG_CONFIG_HOME` if it exists, or the system-wide config file if it does not.
+	pub(crate) fn config_path(&self) -> std::path::PathBuf {
+		if let Some(config) = &self.config {
+			return config.clone();
+		}
+
+		let xdg_config_home =
+			std::env::var_os("XDG_CONFIG_HOME")
+			.map(std::path::PathBuf::from)
+			.filter(|xdg_config_home| xdg_config_home.is_absolute())
+			.or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config")));
+		if let Some(xdg_config_home) = xdg_config_home {
+			let user_config = xdg_config_home.join("tpfancontrol").join("config.toml");
+			if user_config.exists() {
+				return user_config;
+			}
+		}
+
+		crate::model::Config::SYSTEM_PATH.into()
+	}
+}
+
+fn option_value(name: &std::ffi::OsStr, value: Option<std::ffi::OsString>) -> Result<std::ffi::OsString, crate::Error> {
+	value.ok_or_else(|| crate::Error::Usage(format!("{name:?} requires a value")))
+}
+
+fn seconds_option_value(name: &std::ffi::OsStr, value: Option<std::ffi::OsString>) -> Result<std::time::Duration, crate::Error> {
+	let value = option_value(name, value)?;
+	match value.to_str().and_then(|value| value.parse().ok()) {
+		Some(seconds) if seconds > 0 => Ok(std::time::Duration::from_secs(seconds)),
+		_ => Err(crate::Error::Usage(format!("{name:?} requires a positive number of seconds, got {value:?}"))),
+	}
+}
@@ -0,0 +1,523 @@
+/// The default path of the control socket of the daemon
+pub(crate) const DEFAULT_SOCKET_PATH: &str = "/run/tpfancontrol.sock";
+
+/// Requests longer than this are rejected by disconnecting the client.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// The control socket of a running instance.
+///
+/// Clients send one request per line, and receive one JSON object per line in response:
+///
+/// - `get`: The status of the instance, in the same format as `tpfancontrol status --json`
+/// - `set-mode <bios|smart|target|manual>`: Sets the fan mode.
+/// - `set-level <0-7|disengaged>`: Sets the fan level that is used in manual mode. `full-speed` is an alias of `disengaged`.
+/// - `reload`: Loads the config file again.
+/// - `subscribe`: The status of the instance, and then the status again after every update of the fan.
+///
+/// Requests other than `get` and `subscribe` respond with `{"ok":true}`, or `{"ok":false,"error":"..."}` if they failed.
+///
+/// Anyone can connect to the socket to monitor the fan, but only root and the users and groups allowed by the config
+/// can control it.
+#[derive(Debug)]
+pub(crate) struct Server {
+	path: std::path::PathBuf,
+	listener: std::os::unix::net::UnixListener,
+	clients: Vec<Client>,
+}
+
+impl Server {
+	pub(crate) fn bind(path: &std::path::Path) -> Result<Self, crate::Error> {
+		// A socket that nothing is listening on was left behind by an instance that did not exit cleanly, so it can be replaced.
+		match std::os::unix::net::UnixStream::connect(path) {
+			Ok(_) => return Err(crate::Error::ControlSocket(
+				path.to_owned(),
+				std::io::Error::new(std::io::ErrorKind::AddrInUse, "another instance is already listening on it"),
+			)),
+
+			Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+				let is_socket = std::fs::symlink_metadata(path).map_or(false, |metadata| std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type()));
+				if is_socket {
+					std::fs::remove_file(path).map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+				}
+			},
+
+			Err(_) => (),
+		}
+
+		let listener = std::os::unix::net::UnixListener::bind(path).map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+
+		// Whether a client can control the fan is checked against its credentials when it connects.
+		std::fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o666))
+			.map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+
+		listener.set_nonblocking(true).map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+
+		Ok(Server {
+			path: path.to_owned(),
+			listener,
+			clients: vec![],
+		})
+	}
+
+	/// Accepts new clients and handles the requests that have been received from all clients, without blocking.
+	///
+	/// Returns `true` if a request changed the state, so the fan needs to be updated.
+	pub(crate) fn poll(&mut self, state: &mut crate::model::State, args: &crate::cli::Args) -> bool {
+		loop {
+			match self.listener.accept() {
+				Ok((stream, _)) => {
+					if let Err(err) = stream.set_nonblocking(true) {
+						log::warn!("could not accept control socket client: {err}");
+						continue;
+					}
+
+					let (uid, allowed) = match peer_credentials(&stream) {
+						Ok(credentials) => (Some(credentials.uid), is_allowed(&credentials, &state.config)),
+						Err(err) => {
+							log::warn!("could not get credentials of control socket client: {err}");
+							(None, false)
+						},
+					};
+
+					self.clients.push(Client {
+						stream,
+						buffer: vec![],
+						subscribed: false,
+						uid,
+						allowed,
+					});
+				},
+
+				Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+
+				Err(err) => {
+					log::warn!("could not accept control socket client: {err}");
+					break;
+				},
+			}
+		}
+
+		let mut changed = false;
+		self.clients.retain_mut(|client| client.poll(state, args, &mut changed));
+		changed
+	}
+
+	/// Sends the current status to the clients that have subscribed to updates.
+	///
+	/// Clients that do not keep up with the updates are disconnected.
+	pub(crate) fn notify(&mut self, state: &crate::model::State) {
+		if !self.clients.iter().any(|client| client.subscribed) {
+			return;
+		}
+
+		self.clients.retain_mut(|client| !client.subscribed || client.send(&client.status(state)).is_ok());
+	}
+}
+
+impl Drop for Server {
+	fn drop(&mut self) {
+		if let Err(err) = std::fs::remove_file(&self.path) {
+			log::warn!("could not remove control socket {}: {err}", self.path.display());
+		}
+	}
+}
+
+#[derive(Debug)]
+struct Client {
+	stream: std::os::unix::net::UnixStream,
+
+	/// Received data that does not form a complete line yet
+	buffer: Vec<u8>,
+
+	subscribed: bool,
+
+	/// The user of the client, if its credentials could be determined
+	uid: Option<libc::uid_t>,
+
+	/// Whether the client is allowed to control the fan
+	allowed: bool,
+}
+
+impl Client {
+	/// Handles the requests that have been received from this client, without blocking.
+	///
+	/// Returns `false` if the client should be disconnected.
+	fn poll(&mut self, state: &mut crate::model::State, args: &crate::cli::Args, changed: &mut bool) -> bool {
+		let eof = match read_available(&mut self.stream, &mut self.buffer) {
+			Ok(eof) => eof,
+			Err(_) => return false,
+		};
+
+		while let Some(line) = next_line(&mut self.buffer) {
+			if line.is_empty() {
+				continue;
+			}
+
+			let response = match Request::parse(&line) {
+				Ok(request) => self.handle(request, state, args, changed),
+				Err(err) => error_response(&err),
+			};
+
+			if self.send(&response).is_err() {
+				return false;
+			}
+		}
+
+		if self.buffer.len() > MAX_REQUEST_LEN {
+			return false;
+		}
+
+		// A client that has subscribed to updates can close its end of the socket after sending its requests,
+		// and still receive the updates.
+		!eof || self.subscribed
+	}
+
+	fn handle(&mut self, request: Request, state: &mut crate::model::State, args: &crate::cli::Args, changed: &mut bool) -> serde_json::Value {
+		if !self.allowed && !matches!(request, Request::Get | Request::Subscribe) {
+			if let Some(uid) = self.uid {
+				log::info!("denied control socket request from uid {uid}");
+			}
+			else {
+				log::info!("denied control socket request from unknown user");
+			}
+			return error_response("permission denied; the user is not allowed to control the fan by the [control] section of the config");
+		}
+
+		match request {
+			Request::Get => self.status(state),
+
+			Request::SetMode(crate::model::DesiredFanMode::Target) if state.config.target.is_none() =>
+				error_response("target mode requires a [target] section in the config"),
+
+			Request::SetMode(desired_fan_mode) => {
+				log::info!("fan mode set to {desired_fan_mode} via control socket");
+				state.desired_fan_mode = desired_fan_mode;
+				*changed = true;
+				ok_response()
+			},
+
+			Request::SetLevel(desired_manual_fan_level) => {
+				log::info!("manual fan level set to {desired_manual_fan_level} via control socket");
+				state.desired_manual_fan_level = desired_manual_fan_level;
+				*changed = true;
+				ok_response()
+			},
+
+			Request::Reload => match state.reload_config(args) {
+				Ok(()) => {
+					log::info!("reloaded config file {} via control socket", state.config_path.display());
+					*changed = true;
+					ok_response()
+				},
+
+				Err(err) => {
+					log::warn!("could not reload config file via control socket: {err:?}");
+					error_response(&format!("{err:?}"))
+				},
+			},
+
+			Request::Subscribe => {
+				self.subscribed = true;
+				self.status(state)
+			},
+		}
+	}
+
+	fn status(&self, state: &crate::model::State) -> serde_json::Value {
+		let mut status = crate::status::Status::of_running_instance(state);
+		// The client only sees the fan as writable if it's allowed to control it.
+		status.fan_is_writable &= self.allowed;
+		status.to_json()
+	}
+
+	fn send(&mut self, value: &serde_json::Value) -> std::io::Result<()> {
+		let mut line = value.to_string();
+		line.push('\n');
+		std::io::Write::write_all(&mut self.stream, line.as_bytes())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Request {
+	Get,
+	SetMode(crate::model::DesiredFanMode),
+	SetLevel(crate::model::DesiredManualFanLevel),
+	Reload,
+	Subscribe,
+}
+
+impl Request {
+	fn parse(line: &str) -> Result<Self, String> {
+		let mut words = line.split_whitespace();
+
+		let request = match (words.next(), words.next(), words.next()) {
+			(Some("get"), None, None) => Request::Get,
+
+			(Some("set-mode"), Some(desired_fan_mode), None) =>
+				Request::SetMode(desired_fan_mode.parse().map_err(|()| format!("invalid fan mode {desired_fan_mode:?}; expected bios, smart, target or manual"))?),
+
+			(Some("set-level"), Some(desired_manual_fan_level), None) =>
+				Request::SetLevel(desired_manual_fan_level.parse().map_err(|()| format!("invalid fan level {desired_manual_fan_level:?}; expected 0-7 or disengaged"))?),
+
+			(Some("reload"), None, None) => Request::Reload,
+
+			(Some("subscribe"), None, None) => Request::Subscribe,
+
+			_ => return Err(format!("invalid request {line:?}")),
+		};
+
+		Ok(request)
+	}
+}
+
+/// A connection to the control socket of the daemon, that is subscribed to its updates
+#[derive(Debug)]
+pub(crate) struct Connection {
+	path: std::path::PathBuf,
+
+	stream: std::os::unix::net::UnixStream,
+
+	/// Received data that does not form a complete line yet
+	buffer: Vec<u8>,
+
+	/// The number of requests that have been sent but not responded to yet
+	pending_requests: usize,
+
+	/// Why the daemon rejected the last request that it responded to, if it did
+	rejection: Option<String>,
+}
+
+impl Connection {
+	/// Connects to the daemon and subscribes to its updates. Also returns the current status of the daemon.
+	pub(crate) fn subscribe(path: &std::path::Path) -> Result<(Self, crate::status::Status), crate::Error> {
+		const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+		let mut stream = std::os::unix::net::UnixStream::connect(path).map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+
+		std::io::Write::write_all(&mut stream, b"subscribe\n").map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+
+		// Wait for the response with the current status, then read all further updates without blocking.
+		let mut buffer = vec![];
+		stream.set_read_timeout(Some(TIMEOUT)).map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+		while !buffer.contains(&b'\n') {
+			let mut buf = [0_u8; 1024];
+			match std::io::Read::read(&mut stream, &mut buf) {
+				Ok(0) => return Err(crate::Error::ControlSocket(path.to_owned(), std::io::ErrorKind::UnexpectedEof.into())),
+				Ok(len) => buffer.extend_from_slice(&buf[..len]),
+				Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+				Err(err) => return Err(crate::Error::ControlSocket(path.to_owned(), err)),
+			}
+		}
+		stream.set_nonblocking(true).map_err(|err| crate::Error::ControlSocket(path.to_owned(), err))?;
+
+		let mut connection = Connection {
+			path: path.to_owned(),
+			stream,
+			buffer,
+			pending_requests: 0,
+			rejection: None,
+		};
+
+		let status = connection.poll()?.ok_or_else(|| crate::Error::ControlSocket(
+			path.to_owned(),
+			std::io::Error::new(std::io::ErrorKind::InvalidData, "daemon did not respond with its status"),
+		))?;
+
+		Ok((connection, status))
+	}
+
+	/// Handles the responses and updates that have been received from the daemon, without blocking.
+	///
+	/// Returns the latest status that was received, if any.
+	pub(crate) fn poll(&mut self) -> Result<Option<crate::status::Status>, crate::Error> {
+		let eof = read_available(&mut self.stream, &mut self.buffer).map_err(|err| crate::Error::ControlSocket(self.path.clone(), err))?;
+
+		let mut result = None;
+
+		while let Some(line) = next_line(&mut self.buffer) {
+			let value: serde_json::Value =
+				serde_json::from_str(&line)
+				.map_err(|err| crate::Error::ControlSocket(self.path.clone(), std::io::Error::new(std::io::ErrorKind::InvalidData, err)))?;
+
+			if let Some(ok) = value.get("ok").and_then(serde_json::Value::as_bool) {
+				self.pending_requests = self.pending_requests.saturating_sub(1);
+				self.rejection =
+					if ok {
+						None
+					}
+					else {
+						let err = value.get("error").and_then(serde_json::Value::as_str).unwrap_or_default();
+						log::warn!("daemon rejected request: {err}");
+						Some(err.to_owned())
+					};
+			}
+			else {
+				let status = crate::status::Status::from_json(&value).ok_or_else(|| crate::Error::ControlSocket(
+					self.path.clone(),
+					std::io::Error::new(std::io::ErrorKind::InvalidData, "daemon sent a malformed status"),
+				))?;
+				result = Some(status);
+			}
+		}
+
+		if eof {
+			return Err(crate::Error::ControlSocket(
+				self.path.clone(),
+				std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "daemon closed the connection"),
+			));
+		}
+
+		Ok(result)
+	}
+
+	/// Why the daemon rejected the last request that it responded to, if it did
+	pub(crate) fn rejection(&self) -> Option<&str> {
+		self.rejection.as_deref()
+	}
+
+	/// Whether some requests have not been responded to yet, so the statuses that are received might not reflect them yet
+	pub(crate) fn has_pending_requests(&self) -> bool {
+		self.pending_requests > 0
+	}
+
+	pub(crate) fn set_mode(&mut self, desired_fan_mode: crate::model::DesiredFanMode) -> Result<(), crate::Error> {
+		self.send(&format!("set-mode {}", crate::status::fan_mode_name(desired_fan_mode)))
+	}
+
+	pub(crate) fn set_level(&mut self, desired_manual_fan_level: crate::model::DesiredManualFanLevel) -> Result<(), crate::Error> {
+		self.send(&format!("set-level {}", crate::status::fan_level_name(desired_manual_fan_level.into())))
+	}
+
+	/// Asks the daemon to reload its config file.
+	pub(crate) fn reload(&mut self) -> Result<(), crate::Error> {
+		self.send("reload")
+	}
+
+	fn send(&mut self, request: &str) -> Result<(), crate::Error> {
+		let mut line = request.to_owned();
+		line.push('\n');
+		std::io::Write::write_all(&mut self.stream, line.as_bytes()).map_err(|err| crate::Error::ControlSocket(self.path.clone(), err))?;
+		self.pending_requests += 1;
+		Ok(())
+	}
+}
+
+/// Reads all the data that is available from the non-blocking stream into the buffer.
+///
+/// Returns `true` if the other end has closed the stream.
+fn read_available(stream: &mut std::os::unix::net::UnixStream, buffer: &mut Vec<u8>) -> std::io::Result<bool> {
+	let mut buf = [0_u8; 1024];
+	loop {
+		match std::io::Read::read(stream, &mut buf) {
+			Ok(0) => return Ok(true),
+			Ok(len) => buffer.extend_from_slice(&buf[..len]),
+			Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
+			Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+			Err(err) => return Err(err),
+		}
+	}
+}
+
+/// Removes the first complete line from the buffer and returns it, without the surrounding whitespace.
+fn next_line(buffer: &mut Vec<u8>) -> Option<String> {
+	let end = buffer.iter().position(|&b| b == b'\n')?;
+	let line: Vec<_> = buffer.drain(..=end).collect();
+	Some(String::from_utf8_lossy(&line).trim().to_owned())
+}
+
+/// The credentials of the process on the other end of the socket, as of when it connected
+fn peer_credentials(stream: &std::os::unix::net::UnixStream) -> std::io::Result<libc::ucred> {
+	unsafe {
+		let mut credentials: libc::ucred = std::mem::zeroed();
+		let mut len: libc::socklen_t = std::mem::size_of::<libc::ucred>().try_into().expect("ucred is small");
+		if libc::getsockopt(
+			std::os::unix::io::AsRawFd::as_raw_fd(stream),
+			libc::SOL_SOCKET,
+			libc::SO_PEERCRED,
+			std::ptr::addr_of_mut!(credentials).cast(),
+			&mut len,
+		) != 0 {
+			return Err(std::io::Error::last_os_error());
+		}
+
+		Ok(credentials)
+	}
+}
+
+/// Whether a client with the given credentials is allowed to control the fan, ie it's root,
+/// or its user or one of its user's groups is allowed by the config.
+fn is_allowed(credentials: &libc::ucred, config: &crate::model::Config) -> bool {
+	credentials.uid == 0 ||
+	config.allowed_uids.contains(&credentials.uid) ||
+	(!config.allowed_gids.is_empty() && groups(credentials.uid, credentials.gid).iter().any(|gid| config.allowed_gids.contains(gid)))
+}
+
+/// The given primary group and the supplementary groups of the given user, from the user database
+fn groups(uid: libc::uid_t, primary_gid: libc::gid_t) -> Vec<libc::gid_t> {
+	let mut result = vec![primary_gid];
+
+	unsafe {
+		let mut passwd: libc::passwd = std::mem::zeroed();
+		let mut buf: Vec<libc::c_char> = vec![0; 16384];
+		let mut entry = std::ptr::null_mut();
+		if libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut entry) != 0 || entry.is_null() {
+			return result;
+		}
+
+		let mut groups: Vec<libc::gid_t> = vec![0; 64];
+		loop {
+			let mut len: libc::c_int = groups.len().try_into().unwrap_or(libc::c_int::MAX);
+			let succeeded = libc::getgrouplist(passwd.pw_name, primary_gid, groups.as_mut_ptr(), &mut len) >= 0;
+
+			// `len` is now the number of groups of the user, even if `groups` was too small to hold them.
+			let len = usize::try_from(len).unwrap_or_default();
+			if succeeded {
+				groups.truncate(len);
+				result.extend(groups);
+				break;
+			}
+
+			if len <= groups.len() {
+				break;
+			}
+			groups.resize(len, 0);
+		}
+	}
+
+	result
+}
+
+fn ok_response() -> serde_json::Value {
+	serde_json::json!({ "ok": true })
+}
+
+fn error_response(err: &str) -> serde_json::Value {
+	serde_json::json!({ "ok": false, "error": err })
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::model::{DesiredFanMode, DesiredManualFanLevel};
+	use super::Request;
+
+	#[test]
+	fn parse_request() {
+		assert_eq!(Request::parse("get"), Ok(Request::Get));
+		assert_eq!(Request::parse("set-mode manual"), Ok(Request::SetMode(DesiredFanMode::Manual)));
+		assert_eq!(Request::parse("set-mode target"), Ok(Request::SetMode(DesiredFanMode::Target)));
+		assert_eq!(
+			Request::parse("set-level 3"),
+			Ok(Request::SetLevel(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Three))),
+		);
+		assert_eq!(Request::parse("set-level disengaged"), Ok(Request::SetLevel(DesiredManualFanLevel::Disengaged)));
+		assert_eq!(Request::parse("set-level full-speed"), Ok(Request::SetLevel(DesiredManualFanLevel::Disengaged)));
+		assert_eq!(Request::parse("reload"), Ok(Request::Reload));
+		assert_eq!(Request::parse("subscribe"), Ok(Request::Subscribe));
+
+		assert!(Request::parse("set-mode").is_err());
+		assert!(Request::parse("set-mode auto").is_err());
+		assert!(Request::parse("set-level 8").is_err());
+		assert!(Request::parse("get get").is_err());
+		assert!(Request::parse("frobnicate").is_err());
+	}
+}
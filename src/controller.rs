@@ -0,0 +1,951 @@
+/// Decides the level the fan should be set to, based on the temperatures, the config and the desired fan mode.
+#[derive(Debug, Default)]
+pub(crate) struct Controller {
+	/// The fan that this controller chooses the level of, starting from 1, if the fans are controlled separately.
+	/// `None` if it chooses the level of all fans.
+	fan: Option<usize>,
+
+	/// What SMART mode last chose from the `fan_level` table of each curve, if anything.
+	/// The first element is for the top-level `fan_level` table, and the rest are for `Config::curves`.
+	///
+	/// Used to apply the falling bounds.
+	smart_choices: Vec<Option<SmartChoice>>,
+
+	target_state: TargetState,
+
+	/// The level that was last chosen without a read failure. Used by [`crate::model::ReadErrorAction::Hold`]
+	last_fan_level: Option<crate::acpi::FanLevel>,
+
+	/// The number of consecutive updates that had a read failure
+	read_failures: u32,
+}
+
+/// Why the fan mode could not choose a fan level
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ReadFailure {
+	/// The temperatures could not be read
+	Error,
+
+	/// None of the sensors that the fan mode uses have a reading
+	Missing,
+}
+
+impl std::fmt::Display for ReadFailure {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ReadFailure::Error => write!(f, "the temperatures could not be read"),
+			ReadFailure::Missing => write!(f, "the sensors of the fan mode are missing"),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SmartChoice {
+	/// The index of the step in the `fan_level` table
+	Step(usize),
+
+	/// The level that was interpolated from the `fan_level` table, if `Config::interpolate` is set
+	Interpolated(crate::model::DesiredManualFanLevel),
+}
+
+impl Controller {
+	pub(crate) fn new(fan: Option<usize>) -> Self {
+		Controller {
+			fan,
+			..Default::default()
+		}
+	}
+
+	/// Forgets the state of the fan modes, such as when their sections of the config changed
+	pub(crate) fn reset(&mut self) {
+		*self = Controller::new(self.fan);
+	}
+
+	/// Chooses the level of the fan. If the fan mode cannot choose one because the temperatures could not be read,
+	/// the level is chosen according to the `[read_errors]` section of the config instead.
+	pub(crate) fn fan_level(
+		&mut self,
+		now: std::time::Instant,
+		temps: Result<&[Option<crate::acpi::Temp>], &crate::Error>,
+		config: &crate::model::Config,
+		desired_fan_mode: crate::model::DesiredFanMode,
+		desired_manual_fan_level: crate::model::DesiredManualFanLevel,
+	) -> crate::acpi::FanLevel {
+		match self.mode_fan_level(now, temps, config, desired_fan_mode, desired_manual_fan_level) {
+			Ok(fan_level) => {
+				if self.read_failures > config.read_errors.retries {
+					log::info!("temperatures are available again; resuming {desired_fan_mode} mode");
+				}
+
+				self.read_failures = 0;
+				self.last_fan_level = Some(fan_level);
+				fan_level
+			},
+
+			Err(read_failure) => {
+				self.read_failures = self.read_failures.saturating_add(1);
+
+				let action =
+					if self.read_failures <= config.read_errors.retries {
+						crate::model::ReadErrorAction::Hold
+					}
+					else {
+						match read_failure {
+							ReadFailure::Error => config.read_errors.on_error,
+							ReadFailure::Missing => config.read_errors.on_missing,
+						}
+					};
+
+				let fan_level = match action {
+					crate::model::ReadErrorAction::Disengaged => crate::acpi::FanLevel::Disengaged,
+					crate::model::ReadErrorAction::Bios => crate::acpi::FanLevel::Auto,
+					crate::model::ReadErrorAction::Hold => self.last_fan_level.unwrap_or(crate::acpi::FanLevel::Disengaged),
+				};
+
+				if self.read_failures == config.read_errors.retries.saturating_add(1) {
+					log::warn!("{read_failure} for {desired_fan_mode} mode; setting the fan to {fan_level}");
+				}
+
+				fan_level
+			},
+		}
+	}
+
+	/// Whether the last level was not chosen by the fan mode, because the temperatures could not be read
+	/// or the sensors of the fan mode had no reading
+	pub(crate) fn is_read_failing(&self) -> bool {
+		self.read_failures > 0
+	}
+
+	/// The level that the fan mode chooses, if it can choose one
+	fn mode_fan_level(
+		&mut self,
+		now: std::time::Instant,
+		temps: Result<&[Option<crate::acpi::Temp>], &crate::Error>,
+		config: &crate::model::Config,
+		desired_fan_mode: crate::model::DesiredFanMode,
+		desired_manual_fan_level: crate::model::DesiredManualFanLevel,
+	) -> Result<crate::acpi::FanLevel, ReadFailure> {
+		let smart_choices = std::mem::take(&mut self.smart_choices);
+		let mut target_state = std::mem::take(&mut self.target_state);
+
+		match desired_fan_mode {
+			crate::model::DesiredFanMode::Bios => Ok(crate::acpi::FanLevel::Auto),
+
+			crate::model::DesiredFanMode::Smart => self.smart(temps, config, &smart_choices),
+
+			crate::model::DesiredFanMode::Target => match (&config.target, temps) {
+				(Some(target), Ok(temps)) =>
+					if let Some(temp) = temps.get(target.sensor - 1).copied().flatten() {
+						let fan_level = target_state.fan_level(target, temp, now);
+						self.target_state = target_state;
+						Ok(fan_level.into())
+					}
+					else {
+						target_state.hold();
+						self.target_state = target_state;
+						Err(ReadFailure::Missing)
+					},
+
+				(Some(_), Err(_)) => {
+					target_state.hold();
+					self.target_state = target_state;
+					Err(ReadFailure::Error)
+				},
+
+				// The config does not have a `[target]` section, such as because it was removed when the config was reloaded.
+				(None, _) => self.smart(temps, config, &smart_choices),
+			},
+
+			crate::model::DesiredFanMode::Manual => Ok(desired_manual_fan_level.into()),
+		}
+	}
+
+	fn smart(
+		&mut self,
+		temps: Result<&[Option<crate::acpi::Temp>], &crate::Error>,
+		config: &crate::model::Config,
+		smart_choices: &[Option<SmartChoice>],
+	) -> Result<crate::acpi::FanLevel, ReadFailure> {
+		match temps {
+			Ok(temps) => {
+				let (fan_level, smart_choices) = smart_fan_level(temps, config, self.fan, smart_choices);
+				self.smart_choices = smart_choices;
+				fan_level.map(Into::into).ok_or(ReadFailure::Missing)
+			},
+
+			Err(_) => Err(ReadFailure::Error),
+		}
+	}
+}
+
+/// Looks up the highest temperature of the sensors of each curve in that curve's `fan_level` table,
+/// and returns the highest of the resulting levels, along with what was chosen for each curve.
+///
+/// If `fan` is set, only the curves that apply to that fan are used.
+///
+/// Returns `None` for the level if there were no temperatures for any curve.
+fn smart_fan_level(
+	temps: &[Option<crate::acpi::Temp>],
+	config: &crate::model::Config,
+	fan: Option<usize>,
+	current_choices: &[Option<SmartChoice>],
+) -> (Option<crate::model::DesiredManualFanLevel>, Vec<Option<SmartChoice>>) {
+	let curves =
+		std::iter::once((None, &[][..], &config.fan_level))
+		.chain(config.curves.iter().map(|curve| (Some(&curve.sensors), &curve.fans[..], &curve.fan_level)));
+
+	let mut result = None;
+	let mut choices = Vec::with_capacity(config.curves.len() + 1);
+
+	for (i, (sensors, fans, fan_level)) in curves.enumerate() {
+		if let Some(fan) = fan {
+			if !fans.is_empty() && !fans.contains(&fan) {
+				choices.push(None);
+				continue;
+			}
+		}
+
+		let max_temp =
+			temps.iter().enumerate()
+			.filter(|&(index, _)| match sensors {
+				Some(sensors) => sensors.contains(&(index + 1)),
+				None => !config.curves.iter().any(|curve| curve.sensors.contains(&(index + 1))),
+			})
+			.filter_map(|(_, temp)| *temp)
+			.max();
+
+		let current_choice = current_choices.get(i).copied().flatten();
+
+		let choice = max_temp.and_then(|max_temp|
+			if config.interpolate {
+				let current_level = match current_choice {
+					Some(SmartChoice::Interpolated(level)) => Some(level),
+					_ => None,
+				};
+				smart_interpolated_level(fan_level, max_temp, current_level).map(SmartChoice::Interpolated)
+			}
+			else {
+				let current_step = match current_choice {
+					Some(SmartChoice::Step(step)) if step < fan_level.len() => Some(step),
+					_ => None,
+				};
+				smart_step_index(fan_level, max_temp, current_step).map(SmartChoice::Step)
+			});
+		choices.push(choice);
+
+		if max_temp.is_some() {
+			let level = match choice {
+				Some(SmartChoice::Step(step)) => fan_level[step].level,
+				Some(SmartChoice::Interpolated(level)) => level,
+				None => crate::model::DesiredManualFanLevel::Disengaged,
+			};
+			result = std::cmp::max(result, Some(level));
+		}
+	}
+
+	(result, choices)
+}
+
+/// Looks up the temperature in the `fan_level` table of the config.
+///
+/// Returns the index of the highest step whose lower bound the temperature exceeds. Steps at or below the current step
+/// are also chosen if the temperature exceeds their falling bound, so that the fan does not step down until the temperature
+/// has fallen below the current step's falling bound.
+///
+/// Returns `None` if the temperature does not exceed any of them, in which case the fan should be disengaged.
+fn smart_step_index(steps: &[crate::model::FanLevelStep], temp: crate::acpi::Temp, current: Option<usize>) -> Option<usize> {
+	let mut result = None;
+	for (i, step) in steps.iter().enumerate() {
+		let is_at_or_below_current = current.map_or(false, |current| i <= current);
+		if temp > step.lower_bound || (is_at_or_below_current && temp > step.falling_bound) {
+			result = Some(i);
+		}
+	}
+	result
+}
+
+/// Interpolates the temperature between the entries of the `fan_level` table of the config.
+///
+/// The PWM duty cycle of the fan is interpolated linearly between the levels of the two steps whose lower bounds the temperature
+/// is between, and rounded to the nearest firmware level. The fan is only disengaged when the temperature exceeds the lower bound
+/// of a disengaged step. Below it, the disengaged step is treated as the highest firmware level.
+///
+/// If this is lower than the current level, the temperature is interpolated between the falling bounds of the steps instead,
+/// so that the fan does not slow down until the temperature has fallen by as much as the hysteresis.
+///
+/// Returns `None` if the temperature does not exceed any of the lower bounds, in which case the fan should be disengaged.
+fn smart_interpolated_level(
+	steps: &[crate::model::FanLevelStep],
+	temp: crate::acpi::Temp,
+	current: Option<crate::model::DesiredManualFanLevel>,
+) -> Option<crate::model::DesiredManualFanLevel> {
+	let rising = interpolate(steps, temp, |step| step.lower_bound)?;
+
+	match current {
+		Some(current) if rising < current => {
+			let falling = interpolate(steps, temp, |step| step.falling_bound).unwrap_or(rising);
+			Some(std::cmp::max(std::cmp::min(current, falling), rising))
+		},
+
+		_ => Some(rising),
+	}
+}
+
+/// Interpolates the temperature between the steps, using the given bound of each step. See [`smart_interpolated_level`]
+fn interpolate(
+	steps: &[crate::model::FanLevelStep],
+	temp: crate::acpi::Temp,
+	bound: impl Fn(&crate::model::FanLevelStep) -> crate::acpi::Temp,
+) -> Option<crate::model::DesiredManualFanLevel> {
+	fn duty(level: crate::model::DesiredManualFanLevel) -> f64 {
+		match level {
+			crate::model::DesiredManualFanLevel::Firmware(fan_firmware_level) => fan_firmware_level.to_hwmon_level().into(),
+			crate::model::DesiredManualFanLevel::Disengaged => crate::acpi::FanFirmwareLevel::Seven.to_hwmon_level().into(),
+		}
+	}
+
+	let i = steps.iter().rposition(|step| temp > bound(step))?;
+	let step = &steps[i];
+
+	let next_step = match (step.level, steps.get(i + 1)) {
+		(crate::model::DesiredManualFanLevel::Firmware(_), Some(next_step)) if bound(next_step) > bound(step) => next_step,
+		_ => return Some(step.level),
+	};
+
+	let fraction = (*temp.0 - *bound(step).0) / (*bound(next_step).0 - *bound(step).0);
+	let duty = duty(step.level) + fraction * (duty(next_step.level) - duty(step.level));
+	Some(crate::model::DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::nearest(duty)))
+}
+
+/// The highest output of the PID controller of TARGET mode, which is disengaged. See [`crate::model::Target`]
+const MAX_TARGET_OUTPUT: f64 = 8.;
+
+/// The state of the PID controller of TARGET mode
+#[derive(Debug, Default)]
+struct TargetState {
+	/// The integral term. It's limited to the range of the output in either direction so that it does not wind up
+	/// while the fan is already at its lowest or highest level, but can still pull the level down when the temperature overshoots below the target.
+	integral: f64,
+
+	/// The error and the time of the previous update
+	previous: Option<(f64, std::time::Instant)>,
+
+	/// The level that was last chosen, and when it was chosen
+	level: Option<(crate::model::DesiredManualFanLevel, std::time::Instant)>,
+}
+
+impl TargetState {
+	fn fan_level(&mut self, target: &crate::model::Target, temp: crate::acpi::Temp, now: std::time::Instant) -> crate::model::DesiredManualFanLevel {
+		let error = *temp.0 - *target.temperature.0;
+
+		let (dt, derivative) = match self.previous {
+			Some((previous_error, previous_time)) => {
+				let dt = now.saturating_duration_since(previous_time).as_secs_f64();
+				(dt, if dt > 0. { (error - previous_error) / dt } else { 0. })
+			},
+
+			None => (0., 0.),
+		};
+		self.previous = Some((error, now));
+
+		self.integral = (self.integral + target.ki * error * dt).clamp(-MAX_TARGET_OUTPUT, MAX_TARGET_OUTPUT);
+
+		let output = target.kp * error + self.integral + target.kd * derivative;
+		let level = target_output_level(output);
+
+		if let Some((current, since)) = self.level {
+			if level == current || now.saturating_duration_since(since) < target.min_dwell {
+				return current;
+			}
+		}
+
+		self.level = Some((level, now));
+		level
+	}
+
+	/// Keeps the state while the temperature can't be read, but forgets the previous reading
+	/// so that the time without readings is not integrated once they come back.
+	fn hold(&mut self) {
+		self.previous = None;
+	}
+}
+
+/// The nearest fan level to the output of the PID controller of TARGET mode. See [`crate::model::Target`]
+fn target_output_level(output: f64) -> crate::model::DesiredManualFanLevel {
+	if output >= MAX_TARGET_OUTPUT - 0.5 {
+		return crate::model::DesiredManualFanLevel::Disengaged;
+	}
+
+	let (_, fan_firmware_level) =
+		(0_u8..).zip(crate::acpi::FanFirmwareLevel::ALL)
+		.min_by_key(|&(i, _)| ordered_float::NotNan::new((f64::from(i) - output).abs()).unwrap_or_default())
+		.expect("array is not empty");
+	crate::model::DesiredManualFanLevel::Firmware(fan_firmware_level)
+}
+
+/// Limits how fast the fan level changes, according to the `[rate_limit]` section of the config. See [`crate::model::RateLimit`]
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+	/// The level that the fan was last set to, counted as in [`crate::model::RateLimit`], and when it was set to it
+	level: Option<(u8, std::time::Instant)>,
+}
+
+impl RateLimiter {
+	/// Limits the change from the level that the fan was last set to, to the given level.
+	///
+	/// If `rate_limit` is `None`, the level is not limited but is still remembered, so that later changes are limited from it.
+	pub(crate) fn limit(
+		&mut self,
+		now: std::time::Instant,
+		fan_level: crate::acpi::FanLevel,
+		rate_limit: Option<&crate::model::RateLimit>,
+	) -> crate::acpi::FanLevel {
+		let index = match fan_level {
+			crate::acpi::FanLevel::Auto => {
+				self.level = None;
+				return fan_level;
+			},
+
+			crate::acpi::FanLevel::Firmware(fan_firmware_level) =>
+				(0_u8..).zip(crate::acpi::FanFirmwareLevel::ALL)
+				.find_map(|(i, level)| (level == fan_firmware_level).then_some(i))
+				.expect("all firmware levels are in the array"),
+
+			crate::acpi::FanLevel::Disengaged => 8,
+		};
+
+		let index = match (rate_limit, self.level) {
+			(Some(rate_limit), Some((current, since))) =>
+				if index > current {
+					rate_limit.max_step_up.map_or(index, |max_step_up| std::cmp::min(index, current.saturating_add(max_step_up)))
+				}
+				else if index < current && now.saturating_duration_since(since) >= rate_limit.min_dwell {
+					rate_limit.max_step_down.map_or(index, |max_step_down| std::cmp::max(index, current.saturating_sub(max_step_down)))
+				}
+				else {
+					current
+				},
+
+			_ => index,
+		};
+
+		if self.level.map_or(true, |(current, _)| current != index) {
+			self.level = Some((index, now));
+		}
+
+		match crate::acpi::FanFirmwareLevel::ALL.get(usize::from(index)) {
+			Some(&fan_firmware_level) => crate::acpi::FanLevel::Firmware(fan_firmware_level),
+			None => crate::acpi::FanLevel::Disengaged,
+		}
+	}
+}
+
+/// Tracks which sensors are above their critical temperature, according to the `[safety]` section of the config. See [`crate::model::Safety`]
+#[derive(Debug, Default)]
+pub(crate) struct Failsafe {
+	/// The indices of the sensors, starting from 1, that have reached their critical temperature and not yet fallen below their release temperature
+	triggered: Vec<usize>,
+}
+
+impl Failsafe {
+	/// Updates which sensors are above their critical temperature.
+	///
+	/// Sensors that could not be read keep their previous state.
+	pub(crate) fn update(&mut self, temps: &[Option<crate::acpi::Temp>], safety: Option<&crate::model::Safety>) {
+		let was_triggered = self.is_triggered();
+
+		for (i, temp) in temps.iter().enumerate() {
+			let index = i + 1;
+
+			let (temp, thresholds) = match (temp, safety.and_then(|safety| safety.thresholds(index))) {
+				(Some(temp), Some(thresholds)) => (*temp, thresholds),
+				(_, None) => {
+					self.triggered.retain(|&triggered| triggered != index);
+					continue;
+				},
+				(None, Some(_)) => continue,
+			};
+
+			let is_triggered = self.triggered.contains(&index);
+			if !is_triggered && temp >= thresholds.critical {
+				log::warn!(
+					"sensor {index} is at {}, at or above its critical temperature of {}",
+					temp.display(crate::acpi::TempScale::Celsius), thresholds.critical.display(crate::acpi::TempScale::Celsius),
+				);
+				self.triggered.push(index);
+			}
+			else if is_triggered && temp < thresholds.release {
+				log::info!(
+					"sensor {index} is at {}, below its release temperature of {}",
+					temp.display(crate::acpi::TempScale::Celsius), thresholds.release.display(crate::acpi::TempScale::Celsius),
+				);
+				self.triggered.retain(|&triggered| triggered != index);
+			}
+		}
+
+		// Sensors that are no longer read, such as because the config was reloaded
+		self.triggered.retain(|&index| index <= temps.len());
+
+		match (was_triggered, self.is_triggered(), safety) {
+			(false, true, Some(safety)) => log::warn!("failsafe engaged, overriding the fan mode with fan level {}", safety.fan_level),
+			(true, false, _) => log::info!("failsafe released"),
+			_ => (),
+		}
+	}
+
+	/// Whether any sensor is above its critical temperature, in which case the fan must be overridden
+	pub(crate) fn is_triggered(&self) -> bool {
+		!self.triggered.is_empty()
+	}
+
+	/// The indices of the sensors, starting from 1, that are above their critical temperature
+	pub(crate) fn triggered(&self) -> &[usize] {
+		&self.triggered
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::acpi::{FanFirmwareLevel, FanLevel, Temp};
+	use crate::model::{Config, Curve, DesiredFanMode, DesiredManualFanLevel, FanLevelStep};
+
+	fn temp(temp: f64) -> Temp {
+		Temp(temp.try_into().unwrap())
+	}
+
+	fn now() -> std::time::Instant {
+		std::time::Instant::now()
+	}
+
+	fn step(lower_bound: f64, level: DesiredManualFanLevel) -> FanLevelStep {
+		FanLevelStep { lower_bound: temp(lower_bound), falling_bound: temp(lower_bound), level }
+	}
+
+	fn config() -> Config {
+		toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+			3 = 'bat'
+
+			[fan_level]
+			0 = '0'
+			45 = '1'
+			65 = '5'
+			80 = '7'
+			90 = 'full-speed'
+		"#).unwrap()
+	}
+
+	fn config_with_hysteresis(hysteresis: f64) -> Config {
+		let mut config = config();
+		for step in &mut config.fan_level {
+			step.falling_bound = temp(*step.lower_bound.0 - hysteresis);
+		}
+		config
+	}
+
+	fn smart(temps: &[Option<Temp>]) -> FanLevel {
+		super::Controller::default().fan_level(now(), Ok(temps), &config(), DesiredFanMode::Smart, Default::default())
+	}
+
+	#[test]
+	fn bios() {
+		let fan_level = super::Controller::default().fan_level(now(), Ok(&[Some(temp(95.))]), &config(), DesiredFanMode::Bios, Default::default());
+		assert_eq!(fan_level, FanLevel::Auto);
+	}
+
+	#[test]
+	fn manual() {
+		let fan_level = super::Controller::default().fan_level(
+			now(),
+			Ok(&[Some(temp(95.))]),
+			&config(),
+			DesiredFanMode::Manual,
+			DesiredManualFanLevel::Firmware(FanFirmwareLevel::Zero),
+		);
+		assert_eq!(fan_level, FanLevel::Firmware(FanFirmwareLevel::Zero));
+
+		let fan_level = super::Controller::default().fan_level(now(), Ok(&[]), &config(), DesiredFanMode::Manual, DesiredManualFanLevel::Disengaged);
+		assert_eq!(fan_level, FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn smart_uses_max_temp() {
+		assert_eq!(smart(&[Some(temp(30.)), None, Some(temp(50.))]), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(&[Some(temp(70.)), None, Some(temp(50.))]), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(smart(&[Some(temp(85.))]), FanLevel::Firmware(FanFirmwareLevel::Seven));
+		assert_eq!(smart(&[Some(temp(95.))]), FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn smart_lower_bound_is_exclusive() {
+		assert_eq!(smart(&[Some(temp(45.))]), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(smart(&[Some(temp(45.5))]), FanLevel::Firmware(FanFirmwareLevel::One));
+	}
+
+	#[test]
+	fn smart_below_all_lower_bounds_is_disengaged() {
+		assert_eq!(smart(&[Some(temp(0.))]), FanLevel::Disengaged);
+		assert_eq!(smart(&[Some(temp(-5.))]), FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn smart_without_temps_is_disengaged() {
+		assert_eq!(smart(&[]), FanLevel::Disengaged);
+		assert_eq!(smart(&[None, None]), FanLevel::Disengaged);
+
+		let fan_level = super::Controller::default().fan_level(now(), Err(&crate::Error::Enxio), &config(), DesiredFanMode::Smart, Default::default());
+		assert_eq!(fan_level, FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn smart_hysteresis() {
+		let config = config_with_hysteresis(3.);
+		let mut controller = super::Controller::default();
+		let mut smart = |t| controller.fan_level(now(), Ok(&[Some(temp(t))]), &config, DesiredFanMode::Smart, Default::default());
+
+		assert_eq!(smart(64.), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(66.), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(smart(64.), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(smart(62.5), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(smart(62.), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(64.), FanLevel::Firmware(FanFirmwareLevel::One));
+
+		// Falling by several steps at once
+		assert_eq!(smart(85.), FanLevel::Firmware(FanFirmwareLevel::Seven));
+		assert_eq!(smart(43.), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(30.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+	}
+
+	#[test]
+	fn smart_hysteresis_is_reset_by_other_modes() {
+		let config = config_with_hysteresis(3.);
+		let mut controller = super::Controller::default();
+
+		assert_eq!(controller.fan_level(now(), Ok(&[Some(temp(66.))]), &config, DesiredFanMode::Smart, Default::default()), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(controller.fan_level(now(), Ok(&[Some(temp(64.))]), &config, DesiredFanMode::Bios, Default::default()), FanLevel::Auto);
+		assert_eq!(controller.fan_level(now(), Ok(&[Some(temp(64.))]), &config, DesiredFanMode::Smart, Default::default()), FanLevel::Firmware(FanFirmwareLevel::One));
+	}
+
+	#[test]
+	fn smart_interpolate() {
+		let mut config = config();
+		config.interpolate = true;
+		let smart = |t| super::Controller::default().fan_level(now(), Ok(&[Some(temp(t))]), &config, DesiredFanMode::Smart, Default::default());
+
+		// Between level 1 (duty 36) at 45 deg C and level 5 (duty 182) at 65 deg C
+		assert_eq!(smart(45.5), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(50.), FanLevel::Firmware(FanFirmwareLevel::Two));
+		assert_eq!(smart(55.), FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(smart(60.), FanLevel::Firmware(FanFirmwareLevel::Four));
+		assert_eq!(smart(65.5), FanLevel::Firmware(FanFirmwareLevel::Five));
+
+		// The fan is only disengaged above the lower bound of its step
+		assert_eq!(smart(89.), FanLevel::Firmware(FanFirmwareLevel::Seven));
+		assert_eq!(smart(91.), FanLevel::Disengaged);
+
+		assert_eq!(smart(0.), FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn smart_interpolate_hysteresis() {
+		let mut config = config_with_hysteresis(3.);
+		config.interpolate = true;
+		let mut controller = super::Controller::default();
+		let mut smart = |t| controller.fan_level(now(), Ok(&[Some(temp(t))]), &config, DesiredFanMode::Smart, Default::default());
+
+		assert_eq!(smart(55.), FanLevel::Firmware(FanFirmwareLevel::Three));
+		// Would be level 2 when rising
+		assert_eq!(smart(52.), FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(smart(49.), FanLevel::Firmware(FanFirmwareLevel::Two));
+		assert_eq!(smart(60.), FanLevel::Firmware(FanFirmwareLevel::Four));
+	}
+
+	#[test]
+	fn smart_curves() {
+		let mut config = config();
+		config.curves.push(Curve {
+			sensors: vec![3],
+			fans: vec![],
+			fan_level: vec![
+				step(0., DesiredManualFanLevel::Firmware(FanFirmwareLevel::Zero)),
+				step(60., DesiredManualFanLevel::Firmware(FanFirmwareLevel::Two)),
+				step(75., DesiredManualFanLevel::Disengaged),
+			],
+		});
+		let smart = |temps: &[Option<Temp>]| super::Controller::default().fan_level(now(), Ok(temps), &config, DesiredFanMode::Smart, Default::default());
+
+		// Sensor 3 uses its own curve, so it only contributes level 2 at 70 deg C
+		assert_eq!(smart(&[Some(temp(50.)), None, Some(temp(70.))]), FanLevel::Firmware(FanFirmwareLevel::Two));
+		assert_eq!(smart(&[Some(temp(70.)), None, Some(temp(70.))]), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(smart(&[Some(temp(30.)), None, Some(temp(76.))]), FanLevel::Disengaged);
+
+		// Curves without any temperatures do not contribute
+		assert_eq!(smart(&[None, None, Some(temp(70.))]), FanLevel::Firmware(FanFirmwareLevel::Two));
+		assert_eq!(smart(&[Some(temp(50.)), None, None]), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(&[Some(temp(50.))]), FanLevel::Firmware(FanFirmwareLevel::One));
+	}
+
+	#[test]
+	fn smart_curves_separate_fans() {
+		let mut config = config();
+		config.fan_control = crate::model::FanControl::Separate;
+		config.curves.push(Curve {
+			sensors: vec![3],
+			fans: vec![2],
+			fan_level: vec![
+				step(0., DesiredManualFanLevel::Firmware(FanFirmwareLevel::Zero)),
+				step(60., DesiredManualFanLevel::Firmware(FanFirmwareLevel::Six)),
+			],
+		});
+		let smart = |fan, temps: &[Option<Temp>]| super::Controller::new(fan).fan_level(now(), Ok(temps), &config, DesiredFanMode::Smart, Default::default());
+
+		// The curve of sensor 3 only applies to the second fan
+		let temps = [Some(temp(50.)), None, Some(temp(70.))];
+		assert_eq!(smart(Some(1), &temps), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(Some(2), &temps), FanLevel::Firmware(FanFirmwareLevel::Six));
+		assert_eq!(smart(None, &temps), FanLevel::Firmware(FanFirmwareLevel::Six));
+	}
+
+	fn config_with_target(kp: f64, ki: f64, min_dwell: u64) -> Config {
+		let mut config = config();
+		config.target = Some(crate::model::Target {
+			sensor: 1,
+			temperature: temp(70.),
+			kp,
+			ki,
+			kd: 0.,
+			min_dwell: std::time::Duration::from_secs(min_dwell),
+		});
+		config
+	}
+
+	#[test]
+	fn target_proportional() {
+		let config = config_with_target(0.5, 0., 0);
+		let target = |t| super::Controller::default().fan_level(now(), Ok(&[Some(temp(t))]), &config, DesiredFanMode::Target, Default::default());
+
+		assert_eq!(target(60.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(target(70.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(target(76.), FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(target(84.), FanLevel::Firmware(FanFirmwareLevel::Seven));
+		assert_eq!(target(85.), FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn target_integral_anti_windup() {
+		let config = config_with_target(0., 0.1, 0);
+		let start = now();
+		let mut controller = super::Controller::default();
+		let mut target = |seconds, t| controller.fan_level(
+			start + std::time::Duration::from_secs(seconds),
+			Ok(&[Some(temp(t))]),
+			&config,
+			DesiredFanMode::Target,
+			Default::default(),
+		);
+
+		assert_eq!(target(0, 75.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(target(2, 75.), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(target(10, 75.), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(target(100, 75.), FanLevel::Disengaged);
+
+		// The integral was limited to disengaged, so it unwinds as soon as the temperature is below the target.
+		assert_eq!(target(102, 65.), FanLevel::Firmware(FanFirmwareLevel::Seven));
+		assert_eq!(target(110, 65.), FanLevel::Firmware(FanFirmwareLevel::Three));
+	}
+
+	#[test]
+	fn target_integral_is_negative_when_overcooled() {
+		let config = config_with_target(0.5, 0.1, 0);
+		let start = now();
+		let mut controller = super::Controller::default();
+		let mut target = |seconds, t| controller.fan_level(
+			start + std::time::Duration::from_secs(seconds),
+			Ok(&[Some(temp(t))]),
+			&config,
+			DesiredFanMode::Target,
+			Default::default(),
+		);
+
+		assert_eq!(target(0, 60.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(target(100, 60.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+
+		// The integral is -8, so the proportional term alone is not enough to spin the fan up yet.
+		assert_eq!(target(101, 76.), FanLevel::Firmware(FanFirmwareLevel::Zero));
+	}
+
+	#[test]
+	fn target_state_is_kept_on_read_failure() {
+		let mut config = config_with_target(0., 0.1, 0);
+		config.read_errors.retries = 1;
+		let start = now();
+		let mut controller = super::Controller::default();
+		let at = |seconds| start + std::time::Duration::from_secs(seconds);
+
+		assert_eq!(controller.fan_level(at(0), Ok(&[Some(temp(75.))]), &config, DesiredFanMode::Target, Default::default()), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(controller.fan_level(at(10), Ok(&[Some(temp(75.))]), &config, DesiredFanMode::Target, Default::default()), FanLevel::Firmware(FanFirmwareLevel::Five));
+
+		// The level is held while the temperature is missing, and the missing time is not integrated.
+		assert_eq!(controller.fan_level(at(15), Ok(&[None]), &config, DesiredFanMode::Target, Default::default()), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(controller.fan_level(at(100), Ok(&[Some(temp(75.))]), &config, DesiredFanMode::Target, Default::default()), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(controller.fan_level(at(102), Ok(&[Some(temp(75.))]), &config, DesiredFanMode::Target, Default::default()), FanLevel::Firmware(FanFirmwareLevel::Six));
+	}
+
+	#[test]
+	fn target_min_dwell() {
+		let config = config_with_target(1., 0., 10);
+		let start = now();
+		let mut controller = super::Controller::default();
+		let mut target = |seconds, t| controller.fan_level(
+			start + std::time::Duration::from_secs(seconds),
+			Ok(&[Some(temp(t))]),
+			&config,
+			DesiredFanMode::Target,
+			Default::default(),
+		);
+
+		assert_eq!(target(0, 73.), FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(target(5, 75.), FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(target(10, 75.), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(target(15, 71.), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(target(20, 71.), FanLevel::Firmware(FanFirmwareLevel::One));
+	}
+
+	#[test]
+	fn target_without_temp_is_disengaged() {
+		let config = config_with_target(0.5, 0., 0);
+		let fan_level = super::Controller::default().fan_level(now(), Ok(&[None, Some(temp(30.))]), &config, DesiredFanMode::Target, Default::default());
+		assert_eq!(fan_level, FanLevel::Disengaged);
+	}
+
+	#[test]
+	fn target_without_config_is_smart() {
+		let fan_level = super::Controller::default().fan_level(now(), Ok(&[Some(temp(50.))]), &config(), DesiredFanMode::Target, Default::default());
+		assert_eq!(fan_level, FanLevel::Firmware(FanFirmwareLevel::One));
+	}
+
+	#[test]
+	fn rate_limit() {
+		let rate_limit = crate::model::RateLimit {
+			max_step_up: Some(2),
+			max_step_down: Some(1),
+			min_dwell: std::time::Duration::from_secs(10),
+			bypass_temperature: None,
+		};
+		let start = now();
+		let mut rate_limiter = super::RateLimiter::default();
+		let mut limit = |seconds, fan_level, rate_limit| rate_limiter.limit(start + std::time::Duration::from_secs(seconds), fan_level, rate_limit);
+
+		assert_eq!(limit(0, FanLevel::Firmware(FanFirmwareLevel::Zero), Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Zero));
+		assert_eq!(limit(5, FanLevel::Disengaged, Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Two));
+		assert_eq!(limit(10, FanLevel::Disengaged, Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Four));
+
+		// Does not step down until the level has been held for the minimum dwell time, and then only one level at a time
+		assert_eq!(limit(15, FanLevel::Firmware(FanFirmwareLevel::Zero), Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Four));
+		assert_eq!(limit(20, FanLevel::Firmware(FanFirmwareLevel::Zero), Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Three));
+		assert_eq!(limit(25, FanLevel::Firmware(FanFirmwareLevel::Zero), Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Three));
+
+		// Steps up right away even while stepping down is held off
+		assert_eq!(limit(26, FanLevel::Firmware(FanFirmwareLevel::Five), Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Five));
+
+		// Not limited when bypassed, but later changes are limited from the new level
+		assert_eq!(limit(27, FanLevel::Disengaged, None), FanLevel::Disengaged);
+		assert_eq!(limit(40, FanLevel::Firmware(FanFirmwareLevel::Zero), Some(&rate_limit)), FanLevel::Firmware(FanFirmwareLevel::Seven));
+	}
+
+	#[test]
+	fn rate_limit_bypass() {
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+
+			[rate_limit]
+			max_step_up = 1
+			bypass_temperature = 85
+		"#).unwrap();
+		let rate_limit = config.rate_limit.unwrap();
+
+		assert!(!rate_limit.is_bypassed(Ok(&[Some(temp(84.)), None])));
+		assert!(rate_limit.is_bypassed(Ok(&[Some(temp(84.)), Some(temp(85.))])));
+		assert!(rate_limit.is_bypassed(Err(&crate::Error::FanNotWritable)));
+	}
+
+	#[test]
+	fn rate_limit_read_failure() {
+		// The sensors have disappeared, so the level comes from `on_missing` instead of the fan mode, and must not be rate-limited.
+		let config = config();
+		let mut controller = super::Controller::default();
+
+		assert_eq!(controller.fan_level(now(), Ok(&[None, None]), &config, DesiredFanMode::Smart, Default::default()), FanLevel::Disengaged);
+		assert!(controller.is_read_failing());
+
+		assert_eq!(controller.fan_level(now(), Ok(&[Some(temp(50.)), None]), &config, DesiredFanMode::Smart, Default::default()), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert!(!controller.is_read_failing());
+	}
+
+	#[test]
+	fn failsafe() {
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+
+			[safety]
+			critical = 90
+			release = 80
+
+			[safety.sensors]
+			2 = { critical = 60 }
+		"#).unwrap();
+		let safety = config.safety.as_ref();
+		let mut failsafe = super::Failsafe::default();
+
+		failsafe.update(&[Some(temp(89.)), Some(temp(50.))], safety);
+		assert!(!failsafe.is_triggered());
+
+		failsafe.update(&[Some(temp(90.)), Some(temp(60.))], safety);
+		assert_eq!(failsafe.triggered(), [1, 2]);
+
+		// Stays triggered until the temperature falls below the release temperature, which defaults to 5 deg C below the critical one
+		failsafe.update(&[Some(temp(85.)), Some(temp(54.))], safety);
+		assert_eq!(failsafe.triggered(), [1]);
+		failsafe.update(&[None, Some(temp(50.))], safety);
+		assert_eq!(failsafe.triggered(), [1]);
+		failsafe.update(&[Some(temp(79.)), Some(temp(50.))], safety);
+		assert!(!failsafe.is_triggered());
+
+		failsafe.update(&[Some(temp(95.))], safety);
+		assert!(failsafe.is_triggered());
+		failsafe.update(&[Some(temp(95.))], None);
+		assert!(!failsafe.is_triggered());
+	}
+
+	#[test]
+	fn read_errors() {
+		let mut config = config();
+		config.read_errors = crate::model::ReadErrors {
+			on_error: crate::model::ReadErrorAction::Bios,
+			on_missing: crate::model::ReadErrorAction::Hold,
+			retries: 1,
+		};
+		let mut controller = super::Controller::default();
+		let mut smart = |temps: Result<&[Option<Temp>], &crate::Error>| controller.fan_level(now(), temps, &config, DesiredFanMode::Smart, Default::default());
+
+		assert_eq!(smart(Ok(&[Some(temp(50.))])), FanLevel::Firmware(FanFirmwareLevel::One));
+
+		// The last level is held for as many updates as the retries, and then the action applies
+		assert_eq!(smart(Err(&crate::Error::Enxio)), FanLevel::Firmware(FanFirmwareLevel::One));
+		assert_eq!(smart(Err(&crate::Error::Enxio)), FanLevel::Auto);
+		assert_eq!(smart(Ok(&[None])), FanLevel::Firmware(FanFirmwareLevel::One));
+
+		assert_eq!(smart(Ok(&[Some(temp(70.))])), FanLevel::Firmware(FanFirmwareLevel::Five));
+		assert_eq!(smart(Err(&crate::Error::Enxio)), FanLevel::Firmware(FanFirmwareLevel::Five));
+	}
+}
@@ -0,0 +1,86 @@
+/// How often the control socket is checked for requests between updates of the fan
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Runs the fan control loop without a UI, for use as a system service.
+pub(crate) fn run(args: &crate::cli::Args) -> Result<(), crate::Error> {
+	let hwmon = crate::acpi::Hwmon::find(&args.hwmon_root)?;
+	let mut state = crate::model::State::new(args, hwmon)?;
+	if !state.fan_is_writable {
+		return Err(crate::Error::FanNotWritable);
+	}
+
+	log::info!("controlling the fan through {}", state.fan.description());
+	let _restore_auto = crate::acpi::RestoreAutoOnDrop(state.fan.clone());
+
+	let mut server = crate::control::Server::bind(&args.socket)?;
+
+	let mut config_watcher = match crate::watch::FileWatcher::new(&state.config_path) {
+		Ok(config_watcher) => Some(config_watcher),
+		Err(err) => {
+			log::warn!("could not watch config file {} for changes: {err}", state.config_path.display());
+			None
+		},
+	};
+
+	let mut next_update = std::time::Instant::now();
+
+	while !crate::signal::exit_requested() {
+		if std::time::Instant::now() >= next_update {
+			state.update_sensors();
+
+			if let Err(err) = &state.temps {
+				log::warn!("could not read temperatures: {err:?}");
+			}
+
+			// The daemon keeps running so that it can try again at the next update, and reports the error in its status meanwhile.
+			state.control_error = match state.update_fan() {
+				Ok(()) => None,
+				Err(err) => {
+					log::warn!("could not update the fan: {err:?}");
+					Some(format!("{err:?}"))
+				},
+			};
+
+			server.notify(&state);
+
+			next_update = std::time::Instant::now() + state.config.update_interval;
+		}
+
+		let config_changed = match config_watcher.as_mut().map(crate::watch::FileWatcher::poll) {
+			Some(Ok(config_changed)) => config_changed,
+			Some(Err(err)) => {
+				log::warn!("could not watch config file {} for changes: {err}", state.config_path.display());
+				config_watcher = None;
+				false
+			},
+			None => false,
+		};
+
+		let reason =
+			if crate::signal::take_reload_request() { Some("SIGHUP") }
+			else if config_changed { Some("file change") }
+			else { None };
+		if let Some(reason) = reason {
+			match state.reload_config(args) {
+				Ok(()) => log::info!("reloaded config file {} on {reason}", state.config_path.display()),
+				Err(err) => log::warn!("could not reload config file on {reason}, keeping the previous config: {err:?}"),
+			}
+
+			server.notify(&state);
+			next_update = std::time::Instant::now();
+			continue;
+		}
+
+		if server.poll(&mut state, args) {
+			// Apply the change right away instead of at the next update.
+			next_update = std::time::Instant::now();
+			continue;
+		}
+
+		crate::signal::sleep(std::cmp::min(next_update.saturating_duration_since(std::time::Instant::now()), POLL_INTERVAL));
+	}
+
+	log::info!("exiting on signal");
+
+	Ok(())
+}
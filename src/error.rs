@@ -1,17 +1,39 @@
 pub(crate) enum Error {
 	Acpi(std::path::PathBuf, std::io::Error),
-	Config(std::io::Error),
+	Config(std::path::PathBuf, std::io::Error),
+	ConfigCheckFailed(std::path::PathBuf, usize),
+	ControlSocket(std::path::PathBuf, std::io::Error),
 	Enxio,
+	FanNotFound(usize),
+	FanNotWritable,
+	HwmonNotFound(std::path::PathBuf),
 	InitializeUi(std::io::Error),
+	InvalidCurveFan(usize, usize),
+	InvalidWatchdogTimeout(std::time::Duration, std::time::Duration),
+	Signal(std::io::Error),
+	Usage(String),
 }
 
 impl std::fmt::Debug for Error {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
 			Error::Acpi(path, err) => write!(f, "sysfs error with {}: {err}", path.display()),
-			Error::Config(err) => write!(f, "could not parse config file: {err}"),
+			Error::Config(path, err) => write!(f, "could not load config file {}: {err}", path.display()),
+			Error::ConfigCheckFailed(path, num_errors) => write!(f, "config file {} has {num_errors} error(s)", path.display()),
+			Error::ControlSocket(path, err) => write!(f, "control socket error with {}: {err}", path.display()),
 			Error::Enxio => write!(f, "sysfs error: ENXIO"),
+			Error::FanNotFound(index) => write!(f, "fan {index} does not exist"),
+			Error::FanNotWritable => write!(f, "fan is not writable; the daemon must be run as root"),
+			Error::HwmonNotFound(root) => write!(f, "could not find hwmon device for thinkpad_acpi in {}", root.display()),
 			Error::InitializeUi(err) => write!(f, "could not initialize UI: {err}"),
+			Error::InvalidCurveFan(index, num_fans) => write!(f, "curve is for fan {index}, but there are only {num_fans} fan(s)"),
+			Error::InvalidWatchdogTimeout(watchdog_timeout, update_interval) => write!(
+				f,
+				"fan watchdog timeout of {}s must be longer than the update interval of {}s, and at most 120s",
+				watchdog_timeout.as_secs(), update_interval.as_secs(),
+			),
+			Error::Signal(err) => write!(f, "could not install signal handler: {err}"),
+			Error::Usage(message) => write!(f, "{message}\n\n{}", crate::cli::USAGE),
 		}
 	}
 }
@@ -0,0 +1,146 @@
+/// A filter for the readings of a temperature sensor, from the `[filter]` section of the config
+///
+/// The sensors are read once per `update_interval`, both by the daemon and by the UI when the daemon is not running,
+/// so a window of `n` readings covers `n` update intervals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Filter {
+	/// Exponential moving average. Each reading is weighted by `alpha`, and the previous average by `1 - alpha`.
+	Ema { alpha: f64 },
+
+	/// The median of the last `window` readings
+	Median { window: usize },
+
+	/// The highest of the last `window` readings
+	Max { window: usize },
+}
+
+/// The state of the filters of all sensors, ie the previous readings that they depend on
+#[derive(Debug, Default)]
+pub(crate) struct Filters {
+	sensors: Vec<SensorFilterState>,
+}
+
+#[derive(Debug)]
+enum SensorFilterState {
+	None,
+	Ema(crate::acpi::Temp),
+	Window(std::collections::VecDeque<crate::acpi::Temp>),
+}
+
+impl Filters {
+	/// Applies the filters to the new readings of the sensors, and returns the filtered readings.
+	///
+	/// `filters` is indexed by sensor index, starting from 1. Sensors without a filter are not changed.
+	/// A sensor that could not be read has no filtered reading either, and its filter starts over with the next reading.
+	pub(crate) fn apply(&mut self, filters: &[Option<Filter>], temps: &[Option<crate::acpi::Temp>]) -> Vec<Option<crate::acpi::Temp>> {
+		if self.sensors.len() < temps.len() {
+			self.sensors.resize_with(temps.len(), || SensorFilterState::None);
+		}
+
+		temps.iter().zip(&mut self.sensors).enumerate()
+		.map(|(i, (&temp, state))| match (temp, filters.get(i).copied().flatten()) {
+			(Some(temp), Some(filter)) => Some(state.apply(filter, temp)),
+
+			(Some(temp), None) => Some(temp),
+
+			(None, _) => {
+				*state = SensorFilterState::None;
+				None
+			},
+		})
+		.collect()
+	}
+}
+
+impl SensorFilterState {
+	fn apply(&mut self, filter: Filter, temp: crate::acpi::Temp) -> crate::acpi::Temp {
+		match filter {
+			Filter::Ema { alpha } => {
+				let average = match self {
+					SensorFilterState::Ema(average) => *average,
+					_ => temp,
+				};
+				let average = alpha * *temp.0 + (1. - alpha) * *average.0;
+				let average = ordered_float::NotNan::new(average).map_or(temp, crate::acpi::Temp);
+				*self = SensorFilterState::Ema(average);
+				average
+			},
+
+			Filter::Median { window } | Filter::Max { window } => {
+				if !matches!(self, SensorFilterState::Window(_)) {
+					*self = SensorFilterState::Window(Default::default());
+				}
+				let readings = match self {
+					SensorFilterState::Window(readings) => readings,
+					_ => unreachable!(),
+				};
+
+				readings.push_back(temp);
+				while readings.len() > window {
+					readings.pop_front();
+				}
+
+				let mut sorted: Vec<_> = readings.iter().copied().collect();
+				sorted.sort();
+
+				if let Filter::Max { .. } = filter {
+					sorted[sorted.len() - 1]
+				}
+				else if sorted.len() % 2 == 1 {
+					sorted[sorted.len() / 2]
+				}
+				else {
+					let median = (*sorted[sorted.len() / 2 - 1].0 + *sorted[sorted.len() / 2].0) / 2.;
+					ordered_float::NotNan::new(median).map_or(temp, crate::acpi::Temp)
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::acpi::Temp;
+	use super::{Filter, Filters};
+
+	fn temps(temps: &[f64]) -> Vec<Option<Temp>> {
+		temps.iter().map(|&temp| Some(Temp(temp.try_into().unwrap()))).collect()
+	}
+
+	#[test]
+	fn ema() {
+		let filters = [Some(Filter::Ema { alpha: 0.5 })];
+		let mut state = Filters::default();
+
+		assert_eq!(state.apply(&filters, &temps(&[40.])), temps(&[40.]));
+		assert_eq!(state.apply(&filters, &temps(&[80.])), temps(&[60.]));
+		assert_eq!(state.apply(&filters, &temps(&[60.])), temps(&[60.]));
+
+		// Starts over after a missing reading
+		assert_eq!(state.apply(&filters, &[None]), [None]);
+		assert_eq!(state.apply(&filters, &temps(&[80.])), temps(&[80.]));
+	}
+
+	#[test]
+	fn median() {
+		let filters = [None, Some(Filter::Median { window: 3 })];
+		let mut state = Filters::default();
+
+		assert_eq!(state.apply(&filters, &temps(&[10., 40.])), temps(&[10., 40.]));
+		assert_eq!(state.apply(&filters, &temps(&[90., 42.])), temps(&[90., 41.]));
+		// A single spike is ignored
+		assert_eq!(state.apply(&filters, &temps(&[10., 95.])), temps(&[10., 42.]));
+		assert_eq!(state.apply(&filters, &temps(&[10., 44.])), temps(&[10., 44.]));
+	}
+
+	#[test]
+	fn max() {
+		let filters = [Some(Filter::Max { window: 2 })];
+		let mut state = Filters::default();
+
+		assert_eq!(state.apply(&filters, &temps(&[40.])), temps(&[40.]));
+		assert_eq!(state.apply(&filters, &temps(&[50.])), temps(&[50.]));
+		assert_eq!(state.apply(&filters, &temps(&[45.])), temps(&[50.]));
+		assert_eq!(state.apply(&filters, &temps(&[42.])), temps(&[45.]));
+	}
+}
@@ -0,0 +1,138 @@
+/// The recent temperatures of the sensors and speeds of the fan, for showing how they have changed
+#[derive(Debug)]
+pub(crate) struct History {
+	/// The number of readings that are kept
+	len: usize,
+
+	/// The filtered temperatures of each sensor, keyed by sensor index starting from 1
+	temps: std::collections::BTreeMap<usize, std::collections::VecDeque<Option<f64>>>,
+
+	/// The speeds of each fan, starting from the first one
+	fan_speeds: Vec<std::collections::VecDeque<Option<f64>>>,
+}
+
+/// Characters for eighths of the height of a sparkline
+const SPARKLINE_CHARS: [char; 8] = ['\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}', '\u{2585}', '\u{2586}', '\u{2587}', '\u{2588}'];
+
+impl History {
+	pub(crate) fn new(len: usize) -> Self {
+		History {
+			len,
+			temps: Default::default(),
+			fan_speeds: Default::default(),
+		}
+	}
+
+	/// Adds the readings of the status.
+	pub(crate) fn push(&mut self, status: &crate::status::Status) {
+		match &status.sensors {
+			Ok(sensors) => {
+				// Sensors that are no longer in the config, such as because it was reloaded
+				self.temps.retain(|index, _| sensors.iter().any(|sensor| sensor.index == *index));
+
+				for sensor in sensors {
+					let temps = self.temps.entry(sensor.index).or_default();
+					push(temps, sensor.filtered_temp.map(|temp| *temp.0), self.len);
+				}
+			},
+
+			Err(_) =>
+				for temps in self.temps.values_mut() {
+					push(temps, None, self.len);
+				},
+		}
+
+		match &status.fans {
+			Ok(fans) => {
+				self.fan_speeds.resize_with(fans.len(), Default::default);
+				for (fan_speeds, (_, fan_speed)) in self.fan_speeds.iter_mut().zip(fans) {
+					push(fan_speeds, Some(f64::from(fan_speed.0)), self.len);
+				}
+			},
+
+			Err(_) =>
+				for fan_speeds in &mut self.fan_speeds {
+					push(fan_speeds, None, self.len);
+				},
+		}
+	}
+
+	/// A sparkline of the temperatures of the given sensor, starting from 1
+	pub(crate) fn temp_sparkline(&self, index: usize) -> String {
+		self.temps.get(&index).map(sparkline).unwrap_or_default()
+	}
+
+	/// A sparkline of the speeds of the given fan, starting from 1
+	pub(crate) fn fan_speed_sparkline(&self, index: usize) -> String {
+		index.checked_sub(1).and_then(|i| self.fan_speeds.get(i)).map(sparkline).unwrap_or_default()
+	}
+}
+
+fn push(values: &mut std::collections::VecDeque<Option<f64>>, value: Option<f64>, len: usize) {
+	values.push_back(value);
+	while values.len() > len {
+		values.pop_front();
+	}
+}
+
+/// Draws the values as a line of block characters, scaled from the lowest to the highest value. Missing values are drawn as spaces.
+fn sparkline(values: &std::collections::VecDeque<Option<f64>>) -> String {
+	let (min, max) =
+		values.iter().flatten()
+		.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &value| (min.min(value), max.max(value)));
+
+	values.iter()
+	.map(|value| match value {
+		Some(value) if max > min => {
+			let height = (value - min) / (max - min) * 7.;
+			SPARKLINE_CHARS.iter().zip(0_u8..)
+			.find(|&(_, i)| height < f64::from(i) + 0.5)
+			.map_or(SPARKLINE_CHARS[7], |(&c, _)| c)
+		},
+		Some(_) => SPARKLINE_CHARS[0],
+		None => ' ',
+	})
+	.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	#[test]
+	fn sparkline() {
+		let values = [Some(40.), Some(47.), None, Some(54.), Some(40.)].into_iter().collect();
+		assert_eq!(super::sparkline(&values), "\u{2581}\u{2585} \u{2588}\u{2581}");
+
+		let values = [Some(40.), Some(40.)].into_iter().collect();
+		assert_eq!(super::sparkline(&values), "\u{2581}\u{2581}");
+	}
+
+	#[test]
+	fn push() {
+		let mut history = super::History::new(2);
+
+		let status = |temp: f64, fan_speed| crate::status::Status {
+			sensors: Ok(vec![crate::status::SensorStatus {
+				index: 3,
+				name: "cpu".to_owned(),
+				temp: None,
+				filtered_temp: Some(crate::acpi::Temp(temp.try_into().unwrap())),
+			}]),
+			fans: Ok(vec![(crate::acpi::FanLevel::Auto, crate::acpi::FanSpeed(fan_speed))]),
+			fan_is_writable: false,
+			failsafe: vec![],
+			not_obeying: vec![],
+			desired_fan_mode: None,
+			desired_manual_fan_level: None,
+			control_error: None,
+			config_error: None,
+		};
+
+		history.push(&status(40., 2000));
+		history.push(&status(50., 3000));
+		history.push(&status(45., 3000));
+		assert_eq!(history.temp_sparkline(3), "\u{2588}\u{2581}");
+		assert_eq!(history.temp_sparkline(1), "");
+		assert_eq!(history.fan_speed_sparkline(1), "\u{2581}\u{2581}");
+		assert_eq!(history.fan_speed_sparkline(2), "");
+	}
+}
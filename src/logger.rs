@@ -0,0 +1,63 @@
+/// Logs messages to stderr.
+///
+/// While the UI is running, stderr is the terminal that the UI is drawn on,
+/// so messages are buffered and written out once the UI has exited. See [`buffer`].
+struct Logger {
+	buffer: std::sync::Mutex<Option<Vec<String>>>,
+}
+
+static LOGGER: Logger = Logger {
+	buffer: std::sync::Mutex::new(None),
+};
+
+pub(crate) fn init() {
+	// This only fails if a logger has already been set.
+	if log::set_logger(&LOGGER).is_ok() {
+		log::set_max_level(log::LevelFilter::Info);
+	}
+}
+
+/// Buffers log messages until the returned value is dropped, at which point they are written to stderr.
+pub(crate) fn buffer() -> Buffer {
+	*LOGGER.lock() = Some(vec![]);
+	Buffer(())
+}
+
+#[must_use]
+pub(crate) struct Buffer(());
+
+impl Drop for Buffer {
+	fn drop(&mut self) {
+		let buffer = LOGGER.lock().take();
+		for message in buffer.into_iter().flatten() {
+			eprintln!("{message}");
+		}
+	}
+}
+
+impl Logger {
+	fn lock(&self) -> std::sync::MutexGuard<'_, Option<Vec<String>>> {
+		self.buffer.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+	}
+}
+
+impl log::Log for Logger {
+	fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+		metadata.level() <= log::max_level()
+	}
+
+	fn log(&self, record: &log::Record<'_>) {
+		if !self.enabled(record.metadata()) {
+			return;
+		}
+
+		let message = format!("{}: {}", record.level(), record.args());
+		match &mut *self.lock() {
+			Some(buffer) => buffer.push(message),
+			None => eprintln!("{message}"),
+		}
+	}
+
+	fn flush(&self) {
+	}
+}
@@ -1,136 +1,1521 @@
 #[derive(Debug)]
 pub(crate) struct State {
 	pub(crate) config: Config,
+	pub(crate) config_path: std::path::PathBuf,
 
+	/// Why the config file could not be reloaded the last time, if it couldn't. The previous config is still in use in that case.
+	pub(crate) config_error: Option<String>,
+
+	/// The hwmon device, which the temperatures are read from
+	pub(crate) hwmon: crate::acpi::Hwmon,
+
+	/// What the fans are read and controlled through, chosen by the `[fan]` section of the config when the state is created
+	pub(crate) fan: std::rc::Rc<dyn crate::acpi::FanBackend>,
+
+	/// The readings of the sensors, before the filters in the config are applied to them
 	pub(crate) temps: Result<Vec<Option<crate::acpi::Temp>>, crate::Error>,
-	pub(crate) visible_temp_sensors: VisibleTempSensors,
-	pub(crate) temp_scale: crate::acpi::TempScale,
 
-	pub(crate) fan_is_writable: bool,
+	/// The readings of the sensors after the filters in the config are applied to them. These are what the controller uses.
+	pub(crate) filtered_temps: Vec<Option<crate::acpi::Temp>>,
+	pub(crate) filters: crate::filter::Filters,
+
+	pub(crate) fan_is_writable: bool,
+
+	/// The level and speed of each fan, starting from the first one
+	pub(crate) fans: Result<Vec<(crate::acpi::FanLevel, crate::acpi::FanSpeed)>, crate::Error>,
+	pub(crate) desired_fan_mode: DesiredFanMode,
+	pub(crate) desired_manual_fan_level: DesiredManualFanLevel,
+
+	/// One controller and rate limiter for each fan if the fans are controlled separately, or one for all of them otherwise.
+	/// Created on the first update.
+	pub(crate) controllers: Vec<(crate::controller::Controller, crate::controller::RateLimiter)>,
+	pub(crate) failsafe: crate::controller::Failsafe,
+
+	/// The fans that did not take the level they were last set to, even after retrying
+	pub(crate) not_obeying: Vec<FanNotObeying>,
+
+	/// Why the fans could not be controlled at the last update, if they couldn't
+	pub(crate) control_error: Option<String>,
+
+	/// The level that each fan was last set to and took, and when, keyed by fan index starting from 1
+	pub(crate) applied: std::collections::BTreeMap<usize, (crate::acpi::FanLevel, std::time::Instant)>,
+}
+
+/// A fan that did not take the level it was set to, such as because the firmware ignored or overrode it
+#[derive(Clone, Debug)]
+pub(crate) struct FanNotObeying {
+	/// The index of the fan, starting from 1
+	pub(crate) fan: usize,
+
+	pub(crate) requested: crate::acpi::FanLevel,
+
+	/// The level that the fan was read back as, or why it could not be set or read back
+	pub(crate) actual: Result<crate::acpi::FanLevel, String>,
+}
+
+impl std::fmt::Display for FanNotObeying {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match &self.actual {
+			Ok(actual) => write!(f, "fan {} was set to {} but is at {actual}", self.fan, self.requested),
+			Err(err) => write!(f, "fan {} could not be set to {}: {err}", self.fan, self.requested),
+		}
+	}
+}
+
+impl State {
+	pub(crate) fn new(args: &crate::cli::Args, hwmon: crate::acpi::Hwmon) -> Result<Self, crate::Error> {
+		let config_path = args.config_path();
+		let config = Config::load_with_overrides(&config_path, args)?;
+
+		let num_temp_sensors = config.num_temp_sensors();
+		let mut temps = vec![None; num_temp_sensors];
+		let temps = hwmon.read_temps(&mut temps).map(|()| temps);
+
+		let mut filters = crate::filter::Filters::default();
+		let filtered_temps = temps.as_ref().map_or_else(|_| vec![], |temps| filters.apply(&config.filters, temps));
+
+		let fan = config.fan_backend.open(&hwmon, &args.proc_fan);
+		config.check_curve_fans(fan.num_fans())?;
+
+		let fan_is_writable = fan.fan_is_writable()?;
+		let fans = fan.read_fans();
+
+		Ok(State {
+			config,
+			config_path,
+			config_error: None,
+
+			hwmon,
+			fan,
+
+			temps,
+			filtered_temps,
+			filters,
+
+			fan_is_writable,
+
+			fans,
+			desired_fan_mode: Default::default(),
+			desired_manual_fan_level: Default::default(),
+
+			controllers: vec![],
+			failsafe: Default::default(),
+
+			not_obeying: vec![],
+			control_error: None,
+			applied: Default::default(),
+		})
+	}
+
+	/// Loads the config file again, and replaces the current config with it if it's valid.
+	///
+	/// Otherwise the current config is kept, and the error is recorded in `config_error`.
+	pub(crate) fn reload_config(&mut self, args: &crate::cli::Args) -> Result<(), crate::Error> {
+		let config = match Config::load_with_overrides(&self.config_path, args).and_then(|config| {
+			config.check_curve_fans(self.fan.num_fans())?;
+			Ok(config)
+		}) {
+			Ok(config) => config,
+			Err(err) => {
+				self.config_error = Some(format!("{err:?}"));
+				return Err(err);
+			},
+		};
+		self.config_error = None;
+
+		self.apply_config(config);
+
+		Ok(())
+	}
+
+	/// Replaces the current config with the given one.
+	///
+	/// The state of the fan modes and of the filters is only reset if their sections of the config changed.
+	/// The rate limiters are always kept, so that the fan does not jump to a new level right after a reload.
+	fn apply_config(&mut self, config: Config) {
+		if config.fan_backend != self.config.fan_backend {
+			log::warn!("the fan backend in the config file has changed; restart to use it");
+		}
+
+		if let Ok(temps) = &mut self.temps {
+			temps.resize(config.num_temp_sensors(), None);
+		}
+
+		if !config.has_same_fan_modes(&self.config) {
+			for (controller, _) in &mut self.controllers {
+				controller.reset();
+			}
+		}
+
+		if config.filters != self.config.filters {
+			self.filters = Default::default();
+		}
+
+		self.config = config;
+	}
+
+	pub(crate) fn update_sensors(&mut self) {
+		let mut temps = match std::mem::replace(&mut self.temps, Ok(vec![])) {
+			Ok(temps) => temps,
+			Err(_) => vec![None; self.config.num_temp_sensors()],
+		};
+		self.temps = self.hwmon.read_temps(&mut temps[..]).map(|()| temps);
+
+		self.filtered_temps = match &self.temps {
+			Ok(temps) => self.filters.apply(&self.config.filters, temps),
+			Err(_) => {
+				self.filters = Default::default();
+				vec![]
+			},
+		};
+
+		if let Ok(temps) = &self.temps {
+			self.failsafe.update(temps, self.config.safety.as_ref());
+		}
+
+		self.fans = self.fan.read_fans();
+	}
+
+	pub(crate) fn update_fan(&mut self) -> Result<(), crate::Error> {
+		if !self.fan_is_writable {
+			return Ok(());
+		}
+
+		self.fan.arm_watchdog(self.config.watchdog_timeout())?;
+
+		let now = std::time::Instant::now();
+		let temps = self.temps.as_ref().map(|_| &self.filtered_temps[..]);
+
+		// The fans can only be controlled separately if they have their own pwm files.
+		let controllable_fans = self.fan.controllable_fans();
+		let fans: Vec<_> = match self.config.fan_control {
+			FanControl::Separate if controllable_fans.len() > 1 => controllable_fans.iter().copied().map(Some).collect(),
+			FanControl::Separate | FanControl::Together => vec![None],
+		};
+		if self.controllers.len() != fans.len() {
+			self.controllers = fans.iter().map(|&fan| (crate::controller::Controller::new(fan), Default::default())).collect();
+		}
+
+		let mut writes = vec![];
+
+		for (fan, (controller, rate_limiter)) in fans.into_iter().zip(&mut self.controllers) {
+			let fan_level = controller.fan_level(
+				now,
+				temps,
+				&self.config,
+				self.desired_fan_mode,
+				self.desired_manual_fan_level,
+			);
+
+			// The failsafe overrides every mode.
+			let (fan_level, desired_fan_mode) = match (self.failsafe.is_triggered(), &self.config.safety) {
+				(true, Some(safety)) => (safety.fan_level, None),
+				_ => (fan_level, Some(self.desired_fan_mode)),
+			};
+
+			// Only the levels that are chosen automatically are limited. Levels that are chosen by the user, the failsafe
+			// or the `[read_errors]` section of the config apply right away.
+			let rate_limit = match desired_fan_mode {
+				Some(DesiredFanMode::Smart | DesiredFanMode::Target) if !controller.is_read_failing() =>
+					self.config.rate_limit.as_ref().filter(|rate_limit| !rate_limit.is_bypassed(temps)),
+				Some(_) | None => None,
+			};
+			let fan_level = rate_limiter.limit(now, fan_level, rate_limit);
+
+			match fan {
+				Some(fan) => writes.push((fan, fan_level)),
+				None => writes.extend(controllable_fans.iter().map(|&fan| (fan, fan_level))),
+			}
+		}
+
+		let mut not_obeying = vec![];
+
+		for (fan, fan_level) in writes {
+			// Writing the level again when the fan is already at it can make the fan blip on some models,
+			// so it's only written again once in a while in case the firmware changed it without it showing.
+			let observed = self.fans.as_ref().ok().and_then(|fans| fans.get(fan - 1)).map(|&(observed, _)| observed);
+			if !needs_write(self.applied.get(&fan), observed, fan_level, now, self.config.reassert_interval) {
+				continue;
+			}
+
+			match write_fan_verified(&*self.fan, fan, fan_level, self.config.write_retries) {
+				Some(fan_not_obeying) => {
+					let _ = self.applied.remove(&fan);
+					not_obeying.push(fan_not_obeying);
+				},
+				None => {
+					let _ = self.applied.insert(fan, (fan_level, now));
+				},
+			}
+		}
+
+		for fan_not_obeying in &not_obeying {
+			if !self.not_obeying.iter().any(|previous| previous.fan == fan_not_obeying.fan) {
+				log::warn!("{fan_not_obeying}");
+			}
+		}
+		for previous in &self.not_obeying {
+			if !not_obeying.iter().any(|fan_not_obeying| fan_not_obeying.fan == previous.fan) {
+				log::info!("fan {} is obeying again", previous.fan);
+			}
+		}
+		self.not_obeying = not_obeying;
+
+		Ok(())
+	}
+}
+
+/// Whether the fan needs to be set to the level, because it was last set to a different level, it was read as being at a different level,
+/// or it was last set longer than `reassert_interval` ago.
+fn needs_write(
+	applied: Option<&(crate::acpi::FanLevel, std::time::Instant)>,
+	observed: Option<crate::acpi::FanLevel>,
+	fan_level: crate::acpi::FanLevel,
+	now: std::time::Instant,
+	reassert_interval: std::time::Duration,
+) -> bool {
+	match applied {
+		Some(&(applied, applied_at)) =>
+			applied != fan_level ||
+			observed != Some(fan_level) ||
+			now.saturating_duration_since(applied_at) >= reassert_interval,
+		None => true,
+	}
+}
+
+/// Sets the level of the fan and reads it back, and tries again up to `retries` more times if the fan did not take the level.
+///
+/// Returns how the fan did not obey if it still did not take the level.
+fn write_fan_verified(
+	fan_backend: &dyn crate::acpi::FanBackend,
+	fan: usize,
+	fan_level: crate::acpi::FanLevel,
+	retries: u32,
+) -> Option<FanNotObeying> {
+	let mut actual = Err(String::new());
+
+	for _ in 0..=retries {
+		actual = match fan_backend.write_fan(fan, fan_level).and_then(|()| fan_backend.read_fan(fan)) {
+			Ok((actual, _)) if actual == fan_level => return None,
+			Ok((actual, _)) => Ok(actual),
+			Err(err) => Err(format!("{err:?}")),
+		};
+	}
+
+	Some(FanNotObeying {
+		fan,
+		requested: fan_level,
+		actual,
+	})
+}
+
+#[derive(Debug)]
+pub(crate) struct Config {
+	pub(crate) sensors: Vec<Option<String>>,
+
+	/// The `fan_level` table, used for all sensors that are not in any of the `curves`
+	pub(crate) fan_level: Vec<FanLevelStep>,
+
+	pub(crate) curves: Vec<Curve>,
+
+	/// Whether the curves apply to all fans together, or to each fan separately
+	pub(crate) fan_control: FanControl,
+
+	/// What the fans are read and controlled through
+	pub(crate) fan_backend: FanBackendKind,
+
+	/// How many more times the fan is set to a level in one update if it does not take it
+	pub(crate) write_retries: u32,
+
+	/// How often the fan is set to its level again even if it's already at it
+	pub(crate) reassert_interval: std::time::Duration,
+
+	/// The filters of the sensors, indexed by sensor index starting from 1
+	pub(crate) filters: Vec<Option<crate::filter::Filter>>,
+
+	/// The config of TARGET mode, if it's enabled
+	pub(crate) target: Option<Target>,
+
+	/// Limits on how fast SMART and TARGET modes change the fan level, if any
+	pub(crate) rate_limit: Option<RateLimit>,
+
+	/// The critical temperatures above which the fan is overridden in every mode, if any
+	pub(crate) safety: Option<Safety>,
+
+	/// What the fan is set to when the fan mode cannot choose a level because the temperatures could not be read
+	pub(crate) read_errors: ReadErrors,
+
+	/// Whether SMART mode interpolates the fan level between the steps of the `fan_level` tables, instead of using the level of the step
+	pub(crate) interpolate: bool,
+
+	/// How often the daemon updates the fan
+	pub(crate) update_interval: std::time::Duration,
+
+	/// The timeout of the fan watchdog, if set explicitly. See [`Config::watchdog_timeout`]
+	pub(crate) watchdog_timeout: Option<std::time::Duration>,
+
+	/// Users that are allowed to control the fan through the control socket, in addition to root
+	pub(crate) allowed_uids: Vec<libc::uid_t>,
+
+	/// Groups whose members are allowed to control the fan through the control socket
+	pub(crate) allowed_gids: Vec<libc::gid_t>,
+}
+
+/// How far the temperature must fall below the critical temperature of the `[safety]` section before the fan stops being overridden,
+/// if the config does not set a release temperature
+const DEFAULT_SAFETY_RELEASE_DIFFERENCE: f64 = 5.;
+
+/// The longest timeout that the fan watchdog of the thinkpad-acpi kernel module supports
+const MAX_WATCHDOG_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+impl Config {
+	/// The system-wide config file
+	pub(crate) const SYSTEM_PATH: &'static str = "/etc/tpfancontrol/config.toml";
+
+	pub(crate) fn load(path: &std::path::Path) -> Result<Self, crate::Error> {
+		let mut file = std::fs::File::open(path).map_err(|err| crate::Error::Config(path.to_owned(), err))?;
+		let mut config = String::new();
+		let _ = std::io::Read::read_to_string(&mut file, &mut config).map_err(|err| crate::Error::Config(path.to_owned(), err))?;
+		toml::from_str(&config).map_err(|err| crate::Error::Config(path.to_owned(), std::io::Error::new(std::io::ErrorKind::Other, err)))
+	}
+
+	/// Loads the config file and applies the overrides from the command line to it.
+	fn load_with_overrides(path: &std::path::Path, args: &crate::cli::Args) -> Result<Self, crate::Error> {
+		let mut config = Config::load(path)?;
+
+		if let Some(update_interval) = args.update_interval {
+			config.update_interval = update_interval;
+		}
+		if let Some(watchdog_timeout) = args.watchdog_timeout {
+			config.watchdog_timeout = Some(watchdog_timeout);
+		}
+		config.check_watchdog_timeout()?;
+
+		Ok(config)
+	}
+
+	/// The timeout of the fan watchdog. Defaults to twice the update interval.
+	pub(crate) fn watchdog_timeout(&self) -> std::time::Duration {
+		self.watchdog_timeout.unwrap_or(self.update_interval * 2)
+	}
+
+	/// Checks that the fan watchdog will not fire between updates, and that the thinkpad-acpi kernel module supports its timeout.
+	pub(crate) fn check_watchdog_timeout(&self) -> Result<(), crate::Error> {
+		let watchdog_timeout = self.watchdog_timeout();
+		if watchdog_timeout <= self.update_interval || watchdog_timeout > MAX_WATCHDOG_TIMEOUT {
+			return Err(crate::Error::InvalidWatchdogTimeout(watchdog_timeout, self.update_interval));
+		}
+
+		Ok(())
+	}
+
+	/// Checks that the fans of the curves exist, given the number of fans of the fan backend.
+	pub(crate) fn check_curve_fans(&self, num_fans: usize) -> Result<(), crate::Error> {
+		for curve in &self.curves {
+			if let Some(&index) = curve.fans.iter().find(|&&index| index > num_fans) {
+				return Err(crate::Error::InvalidCurveFan(index, num_fans));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Whether SMART and TARGET modes choose the same levels with both configs, ie whether the `[fan_level]`, `[[curve]]`, `[smart]`
+	/// and `[target]` sections are the same.
+	fn has_same_fan_modes(&self, other: &Config) -> bool {
+		self.fan_level == other.fan_level &&
+		self.curves == other.curves &&
+		self.interpolate == other.interpolate &&
+		self.target == other.target
+	}
+
+	/// The number of temperature sensors that need to be read, ie the highest sensor index that the config refers to.
+	pub(crate) fn num_temp_sensors(&self) -> usize {
+		self.curves.iter()
+		.flat_map(|curve| curve.sensors.iter().copied())
+		.chain(self.target.as_ref().map(|target| target.sensor))
+		.chain(self.safety.as_ref().map(|safety| safety.sensors.len()))
+		.fold(self.sensors.len(), std::cmp::max)
+	}
+}
+
+/// The `[target]` section of the config, for TARGET mode.
+///
+/// The output of the PID controller is in fan levels, where 0 to 7 are the firmware levels and 8 is disengaged.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Target {
+	/// The index of the sensor, starting from 1
+	pub(crate) sensor: usize,
+
+	/// The temperature that the sensor is held at
+	pub(crate) temperature: crate::acpi::Temp,
+
+	/// The proportional gain, in fan levels per degree Celsius above the target temperature
+	pub(crate) kp: f64,
+
+	/// The integral gain, in fan levels per degree Celsius per second
+	pub(crate) ki: f64,
+
+	/// The derivative gain, in fan levels per degree Celsius per second of change
+	pub(crate) kd: f64,
+
+	/// How long the fan stays at a level before it can be set to another one
+	pub(crate) min_dwell: std::time::Duration,
+}
+
+/// The `[rate_limit]` section of the config.
+///
+/// Levels are counted with 0 to 7 as the firmware levels and 8 as disengaged.
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+	/// The most levels that the fan is raised by in one update
+	pub(crate) max_step_up: Option<u8>,
+
+	/// The most levels that the fan is lowered by in one update
+	pub(crate) max_step_down: Option<u8>,
+
+	/// How long the fan stays at a level before it can be lowered
+	pub(crate) min_dwell: std::time::Duration,
+
+	/// The temperature at or above which the limits do not apply, so that the fan can react to it right away
+	pub(crate) bypass_temperature: Option<crate::acpi::Temp>,
+}
+
+impl RateLimit {
+	/// Whether the limits do not apply to these temperatures, ie because one of them is at or above the bypass temperature,
+	/// or because they could not be read.
+	pub(crate) fn is_bypassed(&self, temps: Result<&[Option<crate::acpi::Temp>], &crate::Error>) -> bool {
+		match temps {
+			Ok(temps) => self.bypass_temperature.map_or(false, |bypass_temperature| temps.iter().flatten().any(|&temp| temp >= bypass_temperature)),
+			Err(_) => true,
+		}
+	}
+}
+
+/// The `[read_errors]` section of the config
+#[derive(Debug)]
+pub(crate) struct ReadErrors {
+	/// What the fan is set to when the temperatures could not be read
+	pub(crate) on_error: ReadErrorAction,
+
+	/// What the fan is set to when none of the sensors that the fan mode uses have a reading, such as because they disappeared
+	pub(crate) on_missing: ReadErrorAction,
+
+	/// The number of consecutive updates that the fan is held at its last level before `on_error` or `on_missing` apply
+	pub(crate) retries: u32,
+}
+
+impl Default for ReadErrors {
+	fn default() -> Self {
+		ReadErrors {
+			on_error: ReadErrorAction::Disengaged,
+			on_missing: ReadErrorAction::Disengaged,
+			retries: 0,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ReadErrorAction {
+	/// Set the fan to [`crate::acpi::FanLevel::Disengaged`]
+	Disengaged,
+
+	Bios,
+
+	/// Keep the fan at the last level that the fan mode chose
+	Hold,
+}
+
+impl ReadErrorAction {
+	/// The names that [`ReadErrorAction::from_str`](std::str::FromStr::from_str) accepts, for error messages
+	const NAMES: &'static [&'static str] = &[DesiredManualFanLevel::DISENGAGED_NAMES[0], DesiredManualFanLevel::DISENGAGED_NAMES[1], "bios", "hold"];
+}
+
+impl std::str::FromStr for ReadErrorAction {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"bios" => Ok(ReadErrorAction::Bios),
+			"hold" => Ok(ReadErrorAction::Hold),
+			s => match s.parse() {
+				Ok(DesiredManualFanLevel::Disengaged) => Ok(ReadErrorAction::Disengaged),
+				Ok(DesiredManualFanLevel::Firmware(_)) | Err(()) => Err(()),
+			},
+		}
+	}
+}
+
+/// The `[safety]` section of the config
+#[derive(Debug)]
+pub(crate) struct Safety {
+	/// The thresholds of all sensors that do not have their own
+	pub(crate) thresholds: Option<SafetyThresholds>,
+
+	/// The thresholds of specific sensors, indexed by sensor index starting from 1
+	pub(crate) sensors: Vec<Option<SafetyThresholds>>,
+
+	/// The level that the fan is forced to while a sensor is above its critical temperature,
+	/// either [`crate::acpi::FanLevel::Disengaged`] or [`crate::acpi::FanLevel::Auto`]
+	pub(crate) fan_level: crate::acpi::FanLevel,
+}
+
+impl Safety {
+	/// The thresholds of the given sensor, starting from 1
+	pub(crate) fn thresholds(&self, index: usize) -> Option<SafetyThresholds> {
+		self.sensors.get(index - 1).copied().flatten().or(self.thresholds)
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SafetyThresholds {
+	/// The fan is overridden when the temperature reaches this
+	pub(crate) critical: crate::acpi::Temp,
+
+	/// The fan stops being overridden when the temperature falls below this
+	pub(crate) release: crate::acpi::Temp,
+}
+
+/// How the curves apply to the fans of Thinkpads with more than one fan
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum FanControl {
+	/// All fans are set to the highest level of all curves
+	Together,
+
+	/// Each fan is set to the highest level of the curves that apply to it. Fans that do not have their own pwm files
+	/// cannot be controlled separately, in which case they are controlled together.
+	Separate,
+}
+
+impl Default for FanControl {
+	fn default() -> Self {
+		FanControl::Together
+	}
+}
+
+impl std::str::FromStr for FanControl {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"together" => Ok(FanControl::Together),
+			"separate" => Ok(FanControl::Separate),
+			_ => Err(()),
+		}
+	}
+}
+
+/// Which interface of the thinkpad-acpi kernel module the fans are read and controlled through
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum FanBackendKind {
+	/// The hwmon device if it has pwm files, otherwise the fan file in procfs if it exists
+	Auto,
+
+	/// The `pwm*_enable`, `pwm*` and `fan*_input` files of the hwmon device
+	Hwmon,
+
+	/// The fan file in procfs, ie `/proc/acpi/ibm/fan`
+	Procfs,
+}
+
+impl FanBackendKind {
+	/// Opens the backend, given the hwmon device and the path of the fan file in procfs.
+	pub(crate) fn open(self, hwmon: &crate::acpi::Hwmon, proc_fan: &std::path::Path) -> std::rc::Rc<dyn crate::acpi::FanBackend> {
+		let proc_fan = crate::acpi::ProcFan::new(proc_fan.to_owned());
+		match self {
+			FanBackendKind::Auto if !hwmon.has_pwm() && proc_fan.exists() => std::rc::Rc::new(proc_fan),
+			FanBackendKind::Auto | FanBackendKind::Hwmon => std::rc::Rc::new(hwmon.clone()),
+			FanBackendKind::Procfs => std::rc::Rc::new(proc_fan),
+		}
+	}
+}
+
+impl Default for FanBackendKind {
+	fn default() -> Self {
+		FanBackendKind::Auto
+	}
+}
+
+impl std::str::FromStr for FanBackendKind {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"auto" => Ok(FanBackendKind::Auto),
+			"hwmon" => Ok(FanBackendKind::Hwmon),
+			"procfs" => Ok(FanBackendKind::Procfs),
+			_ => Err(()),
+		}
+	}
+}
+
+/// A `fan_level` table that applies to a specific group of sensors
+#[derive(Debug, PartialEq)]
+pub(crate) struct Curve {
+	/// The indices of the sensors, starting from 1.
+	pub(crate) sensors: Vec<usize>,
+
+	/// The indices of the fans that this curve applies to if the fans are controlled separately, starting from 1. Empty for all fans.
+	pub(crate) fans: Vec<usize>,
+
+	pub(crate) fan_level: Vec<FanLevelStep>,
+}
+
+/// An entry in the `fan_level` table of the config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct FanLevelStep {
+	/// The fan is set to this step's level when the temperature rises above this bound.
+	pub(crate) lower_bound: crate::acpi::Temp,
+
+	/// The fan stays at this step's level until the temperature falls to this bound.
+	pub(crate) falling_bound: crate::acpi::Temp,
+
+	pub(crate) level: DesiredManualFanLevel,
+}
+
+impl<'de> serde::Deserialize<'de> for Config {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::de::Deserializer<'de> {
+		struct Inner {
+			sensors: std::collections::HashMap<String, String>,
+			fan_level: std::collections::HashMap<String, FanLevelValue>,
+			smart: Option<SmartInner>,
+			curve: Option<Vec<CurveInner>>,
+			fan: Option<FanInner>,
+			control: Option<ControlInner>,
+			target: Option<TargetInner>,
+			filter: Option<std::collections::HashMap<String, FilterInner>>,
+			rate_limit: Option<RateLimitInner>,
+			safety: Option<SafetyInner>,
+			read_errors: Option<ReadErrorsInner>,
+		}
+
+		// TODO: Replace with `#[derive(serde_derive::Deserialize)]` when https://github.com/rust-lang/rust/issues/55779 is fixed
+		impl<'de> serde::Deserialize<'de> for Inner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = Inner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct Config")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_sensors: Option<_> = None;
+						let mut value_fan_level: Option<_> = None;
+						let mut value_smart: Option<_> = None;
+						let mut value_curve: Option<_> = None;
+						let mut value_fan: Option<_> = None;
+						let mut value_control: Option<_> = None;
+						let mut value_target: Option<_> = None;
+						let mut value_filter: Option<_> = None;
+						let mut value_rate_limit: Option<_> = None;
+						let mut value_safety: Option<_> = None;
+						let mut value_read_errors: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"sensors" => value_sensors = serde::de::MapAccess::next_value(&mut map)?,
+								"fan_level" => value_fan_level = serde::de::MapAccess::next_value(&mut map)?,
+								"smart" => value_smart = serde::de::MapAccess::next_value(&mut map)?,
+								"curve" => value_curve = serde::de::MapAccess::next_value(&mut map)?,
+								"fan" => value_fan = serde::de::MapAccess::next_value(&mut map)?,
+								"control" => value_control = serde::de::MapAccess::next_value(&mut map)?,
+								"target" => value_target = serde::de::MapAccess::next_value(&mut map)?,
+								"filter" => value_filter = serde::de::MapAccess::next_value(&mut map)?,
+								"rate_limit" => value_rate_limit = serde::de::MapAccess::next_value(&mut map)?,
+								"safety" => value_safety = serde::de::MapAccess::next_value(&mut map)?,
+								"read_errors" => value_read_errors = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(Inner {
+							sensors: value_sensors.ok_or_else(|| serde::de::Error::missing_field("sensors"))?,
+							fan_level: value_fan_level.ok_or_else(|| serde::de::Error::missing_field("fan_level"))?,
+							smart: value_smart,
+							curve: value_curve,
+							fan: value_fan,
+							control: value_control,
+							target: value_target,
+							filter: value_filter,
+							rate_limit: value_rate_limit,
+							safety: value_safety,
+							read_errors: value_read_errors,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("Config", &["sensors", "fan_level", "smart", "curve", "fan", "control", "target", "filter", "rate_limit", "safety", "read_errors"], Visitor)
+			}
+		}
+
+		struct SmartInner {
+			hysteresis: Option<f64>,
+			interpolate: Option<bool>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for SmartInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = SmartInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct SmartConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_hysteresis: Option<_> = None;
+						let mut value_interpolate: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"hysteresis" => value_hysteresis = serde::de::MapAccess::next_value(&mut map)?,
+								"interpolate" => value_interpolate = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(SmartInner {
+							hysteresis: value_hysteresis,
+							interpolate: value_interpolate,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("SmartConfig", &["hysteresis", "interpolate"], Visitor)
+			}
+		}
+
+		struct FanInner {
+			update_interval: Option<u64>,
+			watchdog_timeout: Option<u64>,
+			fans: Option<String>,
+			backend: Option<String>,
+			write_retries: Option<u32>,
+			reassert_interval: Option<u64>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for FanInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = FanInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct FanConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_update_interval: Option<_> = None;
+						let mut value_watchdog_timeout: Option<_> = None;
+						let mut value_fans: Option<_> = None;
+						let mut value_backend: Option<_> = None;
+						let mut value_write_retries: Option<_> = None;
+						let mut value_reassert_interval: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"update_interval" => value_update_interval = serde::de::MapAccess::next_value(&mut map)?,
+								"watchdog_timeout" => value_watchdog_timeout = serde::de::MapAccess::next_value(&mut map)?,
+								"fans" => value_fans = serde::de::MapAccess::next_value(&mut map)?,
+								"backend" => value_backend = serde::de::MapAccess::next_value(&mut map)?,
+								"write_retries" => value_write_retries = serde::de::MapAccess::next_value(&mut map)?,
+								"reassert_interval" => value_reassert_interval = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(FanInner {
+							update_interval: value_update_interval,
+							watchdog_timeout: value_watchdog_timeout,
+							fans: value_fans,
+							backend: value_backend,
+							write_retries: value_write_retries,
+							reassert_interval: value_reassert_interval,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("FanConfig", &["update_interval", "watchdog_timeout", "fans", "backend", "write_retries", "reassert_interval"], Visitor)
+			}
+		}
+
+		struct ControlInner {
+			users: Option<Vec<libc::uid_t>>,
+			groups: Option<Vec<libc::gid_t>>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for ControlInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = ControlInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct ControlConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_users: Option<_> = None;
+						let mut value_groups: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"users" => value_users = serde::de::MapAccess::next_value(&mut map)?,
+								"groups" => value_groups = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(ControlInner {
+							users: value_users,
+							groups: value_groups,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("ControlConfig", &["users", "groups"], Visitor)
+			}
+		}
+
+		struct TargetInner {
+			sensor: usize,
+			temperature: f64,
+			kp: Option<f64>,
+			ki: Option<f64>,
+			kd: Option<f64>,
+			min_dwell: Option<u64>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for TargetInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = TargetInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct TargetConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_sensor: Option<_> = None;
+						let mut value_temperature: Option<_> = None;
+						let mut value_proportional: Option<_> = None;
+						let mut value_integral: Option<_> = None;
+						let mut value_derivative: Option<_> = None;
+						let mut value_min_dwell: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"sensor" => value_sensor = serde::de::MapAccess::next_value(&mut map)?,
+								"temperature" => value_temperature = serde::de::MapAccess::next_value(&mut map)?,
+								"kp" => value_proportional = serde::de::MapAccess::next_value(&mut map)?,
+								"ki" => value_integral = serde::de::MapAccess::next_value(&mut map)?,
+								"kd" => value_derivative = serde::de::MapAccess::next_value(&mut map)?,
+								"min_dwell" => value_min_dwell = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(TargetInner {
+							sensor: value_sensor.ok_or_else(|| serde::de::Error::missing_field("sensor"))?,
+							temperature: value_temperature.ok_or_else(|| serde::de::Error::missing_field("temperature"))?,
+							kp: value_proportional,
+							ki: value_integral,
+							kd: value_derivative,
+							min_dwell: value_min_dwell,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("TargetConfig", &["sensor", "temperature", "kp", "ki", "kd", "min_dwell"], Visitor)
+			}
+		}
+
+		struct FilterInner {
+			r#type: String,
+			alpha: Option<f64>,
+			window: Option<usize>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for FilterInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = FilterInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct Filter")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_type: Option<_> = None;
+						let mut value_alpha: Option<_> = None;
+						let mut value_window: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"type" => value_type = serde::de::MapAccess::next_value(&mut map)?,
+								"alpha" => value_alpha = serde::de::MapAccess::next_value(&mut map)?,
+								"window" => value_window = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(FilterInner {
+							r#type: value_type.ok_or_else(|| serde::de::Error::missing_field("type"))?,
+							alpha: value_alpha,
+							window: value_window,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("Filter", &["type", "alpha", "window"], Visitor)
+			}
+		}
+
+		struct RateLimitInner {
+			max_step_up: Option<u8>,
+			max_step_down: Option<u8>,
+			min_dwell: Option<u64>,
+			bypass_temperature: Option<f64>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for RateLimitInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = RateLimitInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct RateLimitConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_max_step_up: Option<_> = None;
+						let mut value_max_step_down: Option<_> = None;
+						let mut value_min_dwell: Option<_> = None;
+						let mut value_bypass_temperature: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"max_step_up" => value_max_step_up = serde::de::MapAccess::next_value(&mut map)?,
+								"max_step_down" => value_max_step_down = serde::de::MapAccess::next_value(&mut map)?,
+								"min_dwell" => value_min_dwell = serde::de::MapAccess::next_value(&mut map)?,
+								"bypass_temperature" => value_bypass_temperature = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(RateLimitInner {
+							max_step_up: value_max_step_up,
+							max_step_down: value_max_step_down,
+							min_dwell: value_min_dwell,
+							bypass_temperature: value_bypass_temperature,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("RateLimitConfig", &["max_step_up", "max_step_down", "min_dwell", "bypass_temperature"], Visitor)
+			}
+		}
+
+		struct ReadErrorsInner {
+			on_error: Option<String>,
+			on_missing: Option<String>,
+			retries: Option<u32>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for ReadErrorsInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = ReadErrorsInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct ReadErrorsConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_on_error: Option<_> = None;
+						let mut value_on_missing: Option<_> = None;
+						let mut value_retries: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"on_error" => value_on_error = serde::de::MapAccess::next_value(&mut map)?,
+								"on_missing" => value_on_missing = serde::de::MapAccess::next_value(&mut map)?,
+								"retries" => value_retries = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(ReadErrorsInner {
+							on_error: value_on_error,
+							on_missing: value_on_missing,
+							retries: value_retries,
+						})
+					}
+				}
+
+				deserializer.deserialize_struct("ReadErrorsConfig", &["on_error", "on_missing", "retries"], Visitor)
+			}
+		}
+
+		fn parse_read_error_action<E>(action: Option<String>) -> Result<ReadErrorAction, E> where E: serde::de::Error {
+			match action {
+				Some(action) => action.parse().map_err(|()| serde::de::Error::unknown_variant(&action, ReadErrorAction::NAMES)),
+				None => Ok(ReadErrorAction::Disengaged),
+			}
+		}
+
+		struct SafetyInner {
+			critical: Option<f64>,
+			release: Option<f64>,
+			action: Option<String>,
+			sensors: Option<std::collections::HashMap<String, SafetyThresholdsInner>>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for SafetyInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = SafetyInner;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct SafetyConfig")
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_critical: Option<_> = None;
+						let mut value_release: Option<_> = None;
+						let mut value_action: Option<_> = None;
+						let mut value_sensors: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"critical" => value_critical = serde::de::MapAccess::next_value(&mut map)?,
+								"release" => value_release = serde::de::MapAccess::next_value(&mut map)?,
+								"action" => value_action = serde::de::MapAccess::next_value(&mut map)?,
+								"sensors" => value_sensors = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(SafetyInner {
+							critical: value_critical,
+							release: value_release,
+							action: value_action,
+							sensors: value_sensors,
+						})
+					}
+				}
 
-	pub(crate) fan: Result<(crate::acpi::FanLevel, crate::acpi::FanSpeed), crate::Error>,
-	pub(crate) desired_fan_mode: DesiredFanMode,
-	pub(crate) desired_manual_fan_level: DesiredManualFanLevel,
-}
+				deserializer.deserialize_struct("SafetyConfig", &["critical", "release", "action", "sensors"], Visitor)
+			}
+		}
 
-impl State {
-	pub(crate) fn new(fan_update_interval: std::time::Duration) -> Result<Self, crate::Error> {
-		let config: Config = {
-			let mut file = std::fs::File::open("/etc/tpfancontrol/config.toml").map_err(crate::Error::Config)?;
-			let mut config = String::new();
-			let _ = std::io::Read::read_to_string(&mut file, &mut config).map_err(crate::Error::Config)?;
-			toml::from_str(&config).map_err(|err| crate::Error::Config(std::io::Error::new(std::io::ErrorKind::Other, err)))?
-		};
+		struct SafetyThresholdsInner {
+			critical: f64,
+			release: Option<f64>,
+		}
 
-		let num_temp_sensors = config.sensors.len();
-		let mut temps = vec![None; num_temp_sensors];
-		let temps = crate::acpi::read_temps(&mut temps).map(|()| temps);
+		impl<'de> serde::Deserialize<'de> for SafetyThresholdsInner {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
 
-		Ok(State {
-			config,
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = SafetyThresholdsInner;
 
-			temps,
-			visible_temp_sensors: Default::default(),
-			temp_scale: Default::default(),
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "struct SafetyThresholds")
+					}
 
-			fan_is_writable: crate::acpi::fan_is_writable(fan_update_interval)?,
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_critical: Option<_> = None;
+						let mut value_release: Option<_> = None;
 
-			fan: crate::acpi::read_fan(),
-			desired_fan_mode: Default::default(),
-			desired_manual_fan_level: Default::default(),
-		})
-	}
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"critical" => value_critical = serde::de::MapAccess::next_value(&mut map)?,
+								"release" => value_release = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
 
-	pub(crate) fn update_sensors(&mut self) {
-		self.temps = std::mem::replace(&mut self.temps, Ok(vec![])).and_then(|mut temps| crate::acpi::read_temps(&mut temps[..]).map(|()| temps));
+						Ok(SafetyThresholdsInner {
+							critical: value_critical.ok_or_else(|| serde::de::Error::missing_field("critical"))?,
+							release: value_release,
+						})
+					}
+				}
 
-		self.fan = crate::acpi::read_fan();
-	}
-}
+				deserializer.deserialize_struct("SafetyThresholds", &["critical", "release"], Visitor)
+			}
+		}
 
-#[derive(Debug)]
-pub(crate) struct Config {
-	pub(crate) sensors: Vec<Option<String>>,
-	pub(crate) fan_level: Vec<(crate::acpi::Temp, DesiredManualFanLevel)>,
-}
+		fn parse_safety_thresholds<E>(critical: f64, release: Option<f64>) -> Result<SafetyThresholds, E> where E: serde::de::Error {
+			let critical =
+				ordered_float::NotNan::new(critical).map(crate::acpi::Temp)
+				.map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Float(critical), &"a temperature in degrees Celsius"))?;
+			let release = match release {
+				Some(release) =>
+					ordered_float::NotNan::new(release).ok()
+					.map(crate::acpi::Temp)
+					.filter(|release| *release <= critical)
+					.ok_or_else(|| serde::de::Error::invalid_value(serde::de::Unexpected::Float(release), &"a temperature in degrees Celsius not higher than the critical temperature"))?,
+				None => crate::acpi::Temp(critical.0 - DEFAULT_SAFETY_RELEASE_DIFFERENCE),
+			};
+			Ok(SafetyThresholds { critical, release })
+		}
 
-impl<'de> serde::Deserialize<'de> for Config {
-	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::de::Deserializer<'de> {
-		struct Inner {
-			sensors: std::collections::HashMap<String, String>,
-			fan_level: std::collections::HashMap<String, String>,
+		struct CurveInner {
+			sensors: Vec<usize>,
+			fans: Option<Vec<usize>>,
+			fan_level: std::collections::HashMap<String, FanLevelValue>,
 		}
 
-		// TODO: Replace with `#[derive(serde_derive::Deserialize)]` when https://github.com/rust-lang/rust/issues/55779 is fixed
-		impl<'de> serde::Deserialize<'de> for Inner {
+		impl<'de> serde::Deserialize<'de> for CurveInner {
 			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
 				struct Visitor;
 
 				impl<'de> serde::de::Visitor<'de> for Visitor {
-					type Value = Inner;
+					type Value = CurveInner;
 
 					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-						write!(f, "struct Config")
+						write!(f, "struct Curve")
 					}
 
 					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
 						let mut value_sensors: Option<_> = None;
+						let mut value_fans: Option<_> = None;
 						let mut value_fan_level: Option<_> = None;
 
 						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
 							match key {
 								"sensors" => value_sensors = serde::de::MapAccess::next_value(&mut map)?,
+								"fans" => value_fans = serde::de::MapAccess::next_value(&mut map)?,
 								"fan_level" => value_fan_level = serde::de::MapAccess::next_value(&mut map)?,
 								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
 							}
 						}
 
-						Ok(Inner {
+						Ok(CurveInner {
 							sensors: value_sensors.ok_or_else(|| serde::de::Error::missing_field("sensors"))?,
+							fans: value_fans,
 							fan_level: value_fan_level.ok_or_else(|| serde::de::Error::missing_field("fan_level"))?,
 						})
 					}
 				}
 
-				deserializer.deserialize_struct("Config", &["sensors", "fan_level"], Visitor)
+				deserializer.deserialize_struct("Curve", &["sensors", "fans", "fan_level"], Visitor)
+			}
+		}
+
+		/// A value in the `fan_level` table. Either just the level, or a table with the level and the falling bound.
+		struct FanLevelValue {
+			level: String,
+			falling: Option<f64>,
+		}
+
+		impl<'de> serde::Deserialize<'de> for FanLevelValue {
+			fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+				struct Visitor;
+
+				impl<'de> serde::de::Visitor<'de> for Visitor {
+					type Value = FanLevelValue;
+
+					fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+						write!(f, "a fan level, or a table with a fan level and a falling temperature")
+					}
+
+					fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> where E: serde::de::Error {
+						Ok(FanLevelValue {
+							level: v.to_owned(),
+							falling: None,
+						})
+					}
+
+					fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+						let mut value_level: Option<_> = None;
+						let mut value_falling: Option<_> = None;
+
+						while let Some(key) = serde::de::MapAccess::next_key(&mut map)? {
+							match key {
+								"level" => value_level = serde::de::MapAccess::next_value(&mut map)?,
+								"falling" => value_falling = serde::de::MapAccess::next_value(&mut map)?,
+								_ => { let _: serde::de::IgnoredAny = serde::de::MapAccess::next_value(&mut map)?; },
+							}
+						}
+
+						Ok(FanLevelValue {
+							level: value_level.ok_or_else(|| serde::de::Error::missing_field("level"))?,
+							falling: value_falling,
+						})
+					}
+				}
+
+				deserializer.deserialize_any(Visitor)
 			}
 		}
 
+		fn parse_temp<E>(s: &str) -> Result<crate::acpi::Temp, E> where E: serde::de::Error {
+			let temp: f64 = s.parse().map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(s), &"a temperature in degrees Celsius"))?;
+			let temp: ordered_float::NotNan<_> = temp.try_into().map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(s), &"a temperature in degrees Celsius"))?;
+			Ok(crate::acpi::Temp(temp))
+		}
+
+		fn parse_fan_level<E>(
+			fan_level: std::collections::HashMap<String, FanLevelValue>,
+			hysteresis: ordered_float::NotNan<f64>,
+		) -> Result<Vec<FanLevelStep>, E> where E: serde::de::Error {
+			let mut result = vec![];
+
+			for (key, value) in fan_level {
+				let lower_bound = parse_temp(&key)?;
+				let falling_bound = match value.falling {
+					Some(falling) =>
+						ordered_float::NotNan::new(falling).ok()
+						.map(crate::acpi::Temp)
+						.filter(|falling_bound| *falling_bound <= lower_bound)
+						.ok_or_else(|| serde::de::Error::invalid_value(serde::de::Unexpected::Float(falling), &"a temperature in degrees Celsius not higher than the fan level's temperature"))?,
+					None => crate::acpi::Temp(lower_bound.0 - hysteresis),
+				};
+				let level = value.level.parse().map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(&value.level), &DesiredManualFanLevel::EXPECTED))?;
+
+				result.push(FanLevelStep { lower_bound, falling_bound, level });
+			}
+
+			result.sort_by_key(|step| step.lower_bound);
+
+			Ok(result)
+		}
+
 		let inner: Inner = serde::Deserialize::deserialize(deserializer)?;
 
 		let mut result = Config {
 			sensors: Default::default(),
 			fan_level: Default::default(),
+			curves: Default::default(),
+			fan_control: Default::default(),
+			fan_backend: Default::default(),
+			write_retries: 2,
+			reassert_interval: std::time::Duration::from_secs(60),
+			filters: Default::default(),
+			target: None,
+			rate_limit: None,
+			safety: None,
+			read_errors: Default::default(),
+			interpolate: false,
+			update_interval: std::time::Duration::from_secs(5),
+			watchdog_timeout: None,
+			allowed_uids: Default::default(),
+			allowed_gids: Default::default(),
 		};
 
 		for (key, value) in inner.sensors {
-			let index = key.parse().map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(&key), &"a sensor index"))?;
+			let index = match key.parse() {
+				Ok(index) if index > 0 => index,
+				_ => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Str(&key), &"a sensor index")),
+			};
 			if result.sensors.len() < index {
 				result.sensors.resize(index, None);
 			}
 			result.sensors[index - 1] = Some(value);
 		}
 
-		for (key, value) in inner.fan_level {
-			let temp: f64 = key.parse().map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(&key), &"a temperature in degrees Celsius"))?;
-			let temp: ordered_float::NotNan<_> = temp.try_into().map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Str(&key), &"a temperature in degrees Celsius"))?;
-			let level = match &*value {
-				"0" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Zero),
-				"1" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::One),
-				"2" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Two),
-				"3" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Three),
-				"4" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Four),
-				"5" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Five),
-				"6" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Six),
-				"7" => DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Seven),
-				"full-speed" => DesiredManualFanLevel::FullSpeed,
-				_ => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Str(&value), &"0-7 or full-speed")),
+		let hysteresis = inner.smart.as_ref().and_then(|smart| smart.hysteresis).unwrap_or(0.);
+		let hysteresis = match ordered_float::NotNan::new(hysteresis) {
+			Ok(hysteresis) if *hysteresis >= 0. => hysteresis,
+			_ => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(hysteresis), &"a non-negative temperature difference in degrees Celsius")),
+		};
+
+		result.fan_level = parse_fan_level(inner.fan_level, hysteresis)?;
+
+		result.interpolate = inner.smart.and_then(|smart| smart.interpolate).unwrap_or(false);
+
+		if let Some(fan) = inner.fan {
+			if let Some(update_interval) = fan.update_interval {
+				if update_interval == 0 {
+					return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"a positive number of seconds"));
+				}
+				result.update_interval = std::time::Duration::from_secs(update_interval);
+			}
+
+			result.watchdog_timeout = fan.watchdog_timeout.map(std::time::Duration::from_secs);
+
+			if let Some(fans) = fan.fans {
+				result.fan_control = fans.parse().map_err(|()| serde::de::Error::unknown_variant(&fans, &["together", "separate"]))?;
+			}
+
+			if let Some(write_retries) = fan.write_retries {
+				result.write_retries = write_retries;
+			}
+
+			if let Some(reassert_interval) = fan.reassert_interval {
+				result.reassert_interval = std::time::Duration::from_secs(reassert_interval);
+			}
+
+			if let Some(backend) = fan.backend {
+				result.fan_backend = backend.parse().map_err(|()| serde::de::Error::unknown_variant(&backend, &["auto", "hwmon", "procfs"]))?;
+			}
+		}
+
+		if let Some(control) = inner.control {
+			result.allowed_uids = control.users.unwrap_or_default();
+			result.allowed_gids = control.groups.unwrap_or_default();
+		}
+
+		if let Some(target) = inner.target {
+			if target.sensor == 0 {
+				return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"a sensor index"));
+			}
+
+			let temperature =
+				ordered_float::NotNan::new(target.temperature)
+				.map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Float(target.temperature), &"a temperature in degrees Celsius"))?;
+
+			let gain = |gain: Option<f64>, default| match gain.unwrap_or(default) {
+				gain if gain >= 0. && gain.is_finite() => Ok(gain),
+				gain => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(gain), &"a non-negative gain")),
+			};
+
+			result.target = Some(Target {
+				sensor: target.sensor,
+				temperature: crate::acpi::Temp(temperature),
+				kp: gain(target.kp, 0.5)?,
+				ki: gain(target.ki, 0.02)?,
+				kd: gain(target.kd, 0.)?,
+				min_dwell: std::time::Duration::from_secs(target.min_dwell.unwrap_or(10)),
+			});
+		}
+
+		if let Some(rate_limit) = inner.rate_limit {
+			let max_step = |max_step: Option<u8>| match max_step {
+				Some(0) => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"a positive number of fan levels")),
+				max_step => Ok(max_step),
+			};
+
+			let bypass_temperature = match rate_limit.bypass_temperature {
+				Some(bypass_temperature) => Some(
+					ordered_float::NotNan::new(bypass_temperature).map(crate::acpi::Temp)
+					.map_err(|_| serde::de::Error::invalid_value(serde::de::Unexpected::Float(bypass_temperature), &"a temperature in degrees Celsius"))?),
+				None => None,
+			};
+
+			result.rate_limit = Some(RateLimit {
+				max_step_up: max_step(rate_limit.max_step_up)?,
+				max_step_down: max_step(rate_limit.max_step_down)?,
+				min_dwell: std::time::Duration::from_secs(rate_limit.min_dwell.unwrap_or(0)),
+				bypass_temperature,
+			});
+		}
+
+		if let Some(read_errors) = inner.read_errors {
+			result.read_errors = ReadErrors {
+				on_error: parse_read_error_action(read_errors.on_error)?,
+				on_missing: parse_read_error_action(read_errors.on_missing)?,
+				retries: read_errors.retries.unwrap_or(0),
+			};
+		}
+
+		if let Some(safety) = inner.safety {
+			let thresholds = match safety.critical {
+				Some(critical) => Some(parse_safety_thresholds(critical, safety.release)?),
+				None if safety.release.is_some() => return Err(serde::de::Error::missing_field("critical")),
+				None => None,
+			};
+
+			let mut sensors = vec![];
+			for (key, value) in safety.sensors.unwrap_or_default() {
+				let index = match key.parse() {
+					Ok(index) if index > 0 => index,
+					_ => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Str(&key), &"a sensor index")),
+				};
+				if sensors.len() < index {
+					sensors.resize(index, None);
+				}
+				sensors[index - 1] = Some(parse_safety_thresholds(value.critical, value.release)?);
+			}
+
+			let fan_level = match safety.action.as_deref() {
+				None => crate::acpi::FanLevel::Disengaged,
+				Some("bios") => crate::acpi::FanLevel::Auto,
+				Some(action) => match action.parse() {
+					Ok(DesiredManualFanLevel::Disengaged) => crate::acpi::FanLevel::Disengaged,
+					Ok(DesiredManualFanLevel::Firmware(_)) | Err(()) => return Err(serde::de::Error::unknown_variant(
+						action,
+						&[DesiredManualFanLevel::DISENGAGED_NAMES[0], DesiredManualFanLevel::DISENGAGED_NAMES[1], "bios"],
+					)),
+				},
+			};
+
+			result.safety = Some(Safety { thresholds, sensors, fan_level });
+		}
+
+		for (key, filter) in inner.filter.unwrap_or_default() {
+			let index = match key.parse() {
+				Ok(index) if index > 0 => index,
+				_ => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Str(&key), &"a sensor index")),
+			};
+
+			let window = || match filter.window {
+				Some(0) => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"a positive number of readings")),
+				Some(window) => Ok(window),
+				None => Err(serde::de::Error::missing_field("window")),
 			};
 
-			result.fan_level.push((crate::acpi::Temp(temp), level));
+			let filter = match &*filter.r#type {
+				"ema" => match filter.alpha {
+					Some(alpha) if alpha > 0. && alpha <= 1. => crate::filter::Filter::Ema { alpha },
+					Some(alpha) => return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(alpha), &"a number greater than 0 and at most 1")),
+					None => return Err(serde::de::Error::missing_field("alpha")),
+				},
+				"median" => crate::filter::Filter::Median { window: window()? },
+				"max" => crate::filter::Filter::Max { window: window()? },
+				r#type => return Err(serde::de::Error::unknown_variant(r#type, &["ema", "median", "max"])),
+			};
+
+			if result.filters.len() < index {
+				result.filters.resize(index, None);
+			}
+			result.filters[index - 1] = Some(filter);
 		}
 
-		result.fan_level.sort_by_key(|(temp, _)| *temp);
+		let mut curve_sensors = std::collections::BTreeSet::new();
+		for curve in inner.curve.unwrap_or_default() {
+			for &index in &curve.sensors {
+				if index == 0 {
+					return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"a sensor index"));
+				}
+
+				if !curve_sensors.insert(index) {
+					return Err(serde::de::Error::custom(format!("sensor {index} is in more than one curve")));
+				}
+			}
+
+			let fans = curve.fans.unwrap_or_default();
+			if fans.contains(&0) {
+				return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"a fan index"));
+			}
+
+			result.curves.push(Curve {
+				sensors: curve.sensors,
+				fans,
+				fan_level: parse_fan_level(curve.fan_level, hysteresis)?,
+			});
+		}
 
 		Ok(result)
 	}
@@ -157,10 +1542,11 @@ impl std::fmt::Display for VisibleTempSensors {
 	}
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub(crate) enum DesiredFanMode {
 	Bios,
 	Smart,
+	Target,
 	Manual,
 }
 
@@ -175,20 +1561,46 @@ impl std::fmt::Display for DesiredFanMode {
 		match self {
 			DesiredFanMode::Bios => write!(f, "BIOS"),
 			DesiredFanMode::Smart => write!(f, "Smart"),
+			DesiredFanMode::Target => write!(f, "Target"),
 			DesiredFanMode::Manual => write!(f, "Manual"),
 		}
 	}
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+impl std::str::FromStr for DesiredFanMode {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"bios" => Ok(DesiredFanMode::Bios),
+			"smart" => Ok(DesiredFanMode::Smart),
+			"target" => Ok(DesiredFanMode::Target),
+			"manual" => Ok(DesiredFanMode::Manual),
+			_ => Err(()),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub(crate) enum DesiredManualFanLevel {
 	Firmware(crate::acpi::FanFirmwareLevel),
-	FullSpeed,
+
+	/// See [`crate::acpi::FanLevel::Disengaged`]
+	Disengaged,
+}
+
+impl DesiredManualFanLevel {
+	/// The names of [`DesiredManualFanLevel::Disengaged`]. The first one is what it's reported as,
+	/// and the second one is its alias from the thinkpad-acpi kernel module.
+	pub(crate) const DISENGAGED_NAMES: [&'static str; 2] = ["disengaged", "full-speed"];
+
+	/// What [`DesiredManualFanLevel::from_str`](std::str::FromStr::from_str) accepts, for error messages
+	pub(crate) const EXPECTED: &'static str = "0-7, disengaged or full-speed";
 }
 
 impl Default for DesiredManualFanLevel {
 	fn default() -> Self {
-		DesiredManualFanLevel::FullSpeed
+		DesiredManualFanLevel::Disengaged
 	}
 }
 
@@ -196,7 +1608,338 @@ impl std::fmt::Display for DesiredManualFanLevel {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
 			DesiredManualFanLevel::Firmware(fan_firmware_level) => write!(f, "{fan_firmware_level}"),
-			DesiredManualFanLevel::FullSpeed => write!(f, "Full speed"),
+			DesiredManualFanLevel::Disengaged => write!(f, "Disengaged"),
+		}
+	}
+}
+
+impl std::str::FromStr for DesiredManualFanLevel {
+	type Err = ();
+
+	/// Parses a fan level as it would be written in the `fan_level` table of the config, ie `0`-`7`, or `disengaged` or its alias `full-speed`
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"0" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Zero)),
+			"1" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::One)),
+			"2" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Two)),
+			"3" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Three)),
+			"4" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Four)),
+			"5" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Five)),
+			"6" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Six)),
+			"7" => Ok(DesiredManualFanLevel::Firmware(crate::acpi::FanFirmwareLevel::Seven)),
+			s if DesiredManualFanLevel::DISENGAGED_NAMES.contains(&s) => Ok(DesiredManualFanLevel::Disengaged),
+			_ => Err(()),
+		}
+	}
+}
+
+impl From<DesiredManualFanLevel> for crate::acpi::FanLevel {
+	fn from(desired_manual_fan_level: DesiredManualFanLevel) -> Self {
+		match desired_manual_fan_level {
+			DesiredManualFanLevel::Firmware(fan_firmware_level) => crate::acpi::FanLevel::Firmware(fan_firmware_level),
+			DesiredManualFanLevel::Disengaged => crate::acpi::FanLevel::Disengaged,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::acpi::{FanFirmwareLevel, Temp};
+	use super::{Config, DesiredManualFanLevel};
+
+	fn temp(temp: f64) -> Temp {
+		Temp(temp.try_into().unwrap())
+	}
+
+	#[test]
+	fn config_example() {
+		let config: Config = toml::from_str(include_str!("../config.toml.example")).unwrap();
+		assert_eq!(config.sensors.len(), 12);
+		assert_eq!(config.allowed_uids, [1000]);
+		assert!(config.allowed_gids.is_empty());
+		assert_eq!(config.target.map(|target| target.sensor), Some(1));
+	}
+
+	#[test]
+	fn config_filters() {
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+
+			[filter]
+			1 = { type = 'median', window = 5 }
+			3 = { type = 'ema', alpha = 0.25 }
+		"#).unwrap();
+
+		assert_eq!(config.filters, [
+			Some(crate::filter::Filter::Median { window: 5 }),
+			None,
+			Some(crate::filter::Filter::Ema { alpha: 0.25 }),
+		]);
+
+		for filter in ["{ type = 'ema', alpha = 0 }", "{ type = 'ema' }", "{ type = 'max', window = 0 }", "{ type = 'mean', window = 3 }"] {
+			let result: Result<Config, _> = toml::from_str(&format!("[sensors]\n1 = 'cpu'\n[fan_level]\n0 = '0'\n[filter]\n1 = {filter}\n"));
+			assert!(result.is_err(), "{filter}");
+		}
+	}
+
+	#[test]
+	fn config_fan_level_falling_bound() {
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+			45 = '1'
+			65 = { level = '5', falling = 60 }
+			80 = '7'
+
+			[smart]
+			hysteresis = 3
+		"#).unwrap();
+
+		let fan_level: Vec<_> = config.fan_level.iter().map(|step| (step.lower_bound, step.falling_bound, step.level)).collect();
+		assert_eq!(fan_level, [
+			(temp(0.), temp(-3.), DesiredManualFanLevel::Firmware(FanFirmwareLevel::Zero)),
+			(temp(45.), temp(42.), DesiredManualFanLevel::Firmware(FanFirmwareLevel::One)),
+			(temp(65.), temp(60.), DesiredManualFanLevel::Firmware(FanFirmwareLevel::Five)),
+			(temp(80.), temp(77.), DesiredManualFanLevel::Firmware(FanFirmwareLevel::Seven)),
+		]);
+
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+			45 = '1'
+		"#).unwrap();
+
+		assert!(config.fan_level.iter().all(|step| step.falling_bound == step.lower_bound));
+	}
+
+	#[test]
+	fn config_fan_level_falling_bound_above_lower_bound() {
+		let result: Result<Config, _> = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			65 = { level = '5', falling = 70 }
+		"#);
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn config_curves() {
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+			7 = 'bat'
+
+			[fan_level]
+			0 = '0'
+			45 = '1'
+
+			[[curve]]
+			sensors = [7, 9]
+
+			[curve.fan_level]
+			0 = '0'
+			50 = '3'
+		"#).unwrap();
+
+		assert_eq!(config.curves.len(), 1);
+		assert_eq!(config.curves[0].sensors, [7, 9]);
+		assert!(config.curves[0].fans.is_empty());
+		assert_eq!(config.curves[0].fan_level.len(), 2);
+		assert_eq!(config.num_temp_sensors(), 9);
+		assert_eq!(config.fan_control, super::FanControl::Together);
+
+		let config: Config = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+			2 = 'gpu'
+
+			[fan_level]
+			0 = '0'
+
+			[[curve]]
+			sensors = [2]
+			fans = [2]
+			fan_level = { 0 = '1' }
+
+			[fan]
+			fans = 'separate'
+			backend = 'procfs'
+		"#).unwrap();
+		assert_eq!(config.curves[0].fans, [2]);
+		assert_eq!(config.fan_control, super::FanControl::Separate);
+		assert_eq!(config.fan_backend, super::FanBackendKind::Procfs);
+
+		// The procfs backend only has one fan.
+		assert!(config.check_curve_fans(2).is_ok());
+		assert!(matches!(config.check_curve_fans(1), Err(crate::Error::InvalidCurveFan(2, 1))));
+
+		let result: Result<Config, _> = toml::from_str(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+
+			[[curve]]
+			sensors = [1]
+			fan_level = { 0 = '1' }
+
+			[[curve]]
+			sensors = [1]
+			fan_level = { 0 = '2' }
+		"#);
+		assert!(result.is_err());
+	}
+
+	/// A fan that ignores the given number of writes, and is at level 3 until it takes one
+	#[derive(Debug)]
+	struct IgnoringFan {
+		writes_to_ignore: std::cell::Cell<u32>,
+		level: std::cell::Cell<crate::acpi::FanLevel>,
+	}
+
+	impl crate::acpi::FanBackend for IgnoringFan {
+		fn description(&self) -> String {
+			"ignoring fan".to_owned()
+		}
+
+		fn num_fans(&self) -> usize {
+			1
+		}
+
+		fn controllable_fans(&self) -> Vec<usize> {
+			vec![1]
 		}
+
+		fn read_fan(&self, _: usize) -> Result<(crate::acpi::FanLevel, crate::acpi::FanSpeed), crate::Error> {
+			Ok((self.level.get(), crate::acpi::FanSpeed(2000)))
+		}
+
+		fn fan_is_writable(&self) -> Result<bool, crate::Error> {
+			Ok(true)
+		}
+
+		fn arm_watchdog(&self, _: std::time::Duration) -> Result<(), crate::Error> {
+			Ok(())
+		}
+
+		fn write_fan(&self, _: usize, fan_level: crate::acpi::FanLevel) -> Result<(), crate::Error> {
+			match self.writes_to_ignore.get().checked_sub(1) {
+				Some(writes_to_ignore) => self.writes_to_ignore.set(writes_to_ignore),
+				None => self.level.set(fan_level),
+			}
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn write_fan_verified() {
+		let fan = |writes_to_ignore| IgnoringFan {
+			writes_to_ignore: std::cell::Cell::new(writes_to_ignore),
+			level: std::cell::Cell::new(crate::acpi::FanLevel::Firmware(FanFirmwareLevel::Three)),
+		};
+		let fan_level = crate::acpi::FanLevel::Firmware(FanFirmwareLevel::Five);
+
+		assert!(super::write_fan_verified(&fan(0), 1, fan_level, 0).is_none());
+		assert!(super::write_fan_verified(&fan(2), 1, fan_level, 2).is_none());
+
+		let fan_not_obeying = super::write_fan_verified(&fan(3), 1, fan_level, 2).unwrap();
+		assert_eq!(fan_not_obeying.requested, fan_level);
+		assert_eq!(fan_not_obeying.actual, Ok(crate::acpi::FanLevel::Firmware(FanFirmwareLevel::Three)));
+	}
+
+	#[test]
+	fn reload_keeps_rate_limit() {
+		let root = std::env::temp_dir().join(format!("tpfancontrol-test-{}-reload", std::process::id()));
+		std::fs::create_dir_all(&root).unwrap();
+		std::fs::write(root.join("name"), "thinkpad\n").unwrap();
+		std::fs::write(root.join("temp1_input"), "30000\n").unwrap();
+
+		let config = || toml::from_str::<Config>(r#"
+			[sensors]
+			1 = 'cpu'
+
+			[fan_level]
+			0 = '0'
+			40 = '7'
+
+			[rate_limit]
+			max_step_up = 1
+		"#).unwrap();
+
+		let mut state = super::State {
+			config: config(),
+			config_path: Default::default(),
+			config_error: None,
+			hwmon: crate::acpi::Hwmon::find(&root).unwrap(),
+			fan: std::rc::Rc::new(IgnoringFan {
+				writes_to_ignore: std::cell::Cell::new(0),
+				level: std::cell::Cell::new(crate::acpi::FanLevel::Auto),
+			}),
+			temps: Ok(vec![None]),
+			filtered_temps: vec![],
+			filters: Default::default(),
+			fan_is_writable: true,
+			fans: Ok(vec![]),
+			desired_fan_mode: super::DesiredFanMode::Smart,
+			desired_manual_fan_level: Default::default(),
+			controllers: vec![],
+			failsafe: Default::default(),
+			not_obeying: vec![],
+			control_error: None,
+			applied: Default::default(),
+		};
+
+		state.update_sensors();
+		state.update_fan().unwrap();
+		assert_eq!(state.fan.read_fan(1).unwrap().0, crate::acpi::FanLevel::Firmware(FanFirmwareLevel::Zero));
+
+		// The fan is still raised by one level at a time after the same config is loaded again.
+		std::fs::write(root.join("temp1_input"), "50000\n").unwrap();
+		state.apply_config(config());
+		state.update_sensors();
+		state.update_fan().unwrap();
+		assert_eq!(state.fan.read_fan(1).unwrap().0, crate::acpi::FanLevel::Firmware(FanFirmwareLevel::One));
+
+		drop(std::fs::remove_dir_all(&root));
+	}
+
+	#[test]
+	fn needs_write() {
+		let three = crate::acpi::FanLevel::Firmware(FanFirmwareLevel::Three);
+		let five = crate::acpi::FanLevel::Firmware(FanFirmwareLevel::Five);
+		let applied_at = std::time::Instant::now();
+		let reassert_interval = std::time::Duration::from_secs(60);
+		let soon = applied_at + std::time::Duration::from_secs(5);
+		let later = applied_at + reassert_interval;
+
+		// Never set
+		assert!(super::needs_write(None, Some(five), five, soon, reassert_interval));
+
+		// Already at the level
+		assert!(!super::needs_write(Some(&(five, applied_at)), Some(five), five, soon, reassert_interval));
+
+		// A different level
+		assert!(super::needs_write(Some(&(three, applied_at)), Some(three), five, soon, reassert_interval));
+
+		// Changed by something else, or could not be read
+		assert!(super::needs_write(Some(&(five, applied_at)), Some(three), five, soon, reassert_interval));
+		assert!(super::needs_write(Some(&(five, applied_at)), None, five, soon, reassert_interval));
+
+		// Set again once in a while
+		assert!(super::needs_write(Some(&(five, applied_at)), Some(five), five, later, reassert_interval));
+		assert!(super::needs_write(Some(&(five, applied_at)), Some(five), five, soon, std::time::Duration::ZERO));
 	}
 }
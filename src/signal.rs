@@ -0,0 +1,55 @@
+static EXIT_REQUESTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+static RELOAD_REQUESTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Installs handlers for SIGINT, SIGTERM and SIGHUP, so that the process can restore BIOS control of the fan before exiting.
+/// Use [`exit_requested`] to check if one of these signals has been received.
+///
+/// If `reload_on_sighup` is set, SIGHUP requests the config file to be reloaded instead. Use [`take_reload_request`] to check for it.
+pub(crate) fn install_handlers(reload_on_sighup: bool) -> Result<(), crate::Error> {
+	extern "C" fn handle_exit_signal(_: libc::c_int) {
+		EXIT_REQUESTED.store(true, std::sync::atomic::Ordering::SeqCst);
+	}
+
+	extern "C" fn handle_reload_signal(_: libc::c_int) {
+		RELOAD_REQUESTED.store(true, std::sync::atomic::Ordering::SeqCst);
+	}
+
+	for signum in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+		let handler: extern "C" fn(libc::c_int) =
+			if reload_on_sighup && signum == libc::SIGHUP { handle_reload_signal } else { handle_exit_signal };
+
+		unsafe {
+			let mut action: libc::sigaction = std::mem::zeroed();
+			action.sa_sigaction = handler as libc::sighandler_t;
+			if libc::sigemptyset(&mut action.sa_mask) != 0 || libc::sigaction(signum, &action, std::ptr::null_mut()) != 0 {
+				return Err(crate::Error::Signal(std::io::Error::last_os_error()));
+			}
+		}
+	}
+
+	Ok(())
+}
+
+pub(crate) fn exit_requested() -> bool {
+	EXIT_REQUESTED.load(std::sync::atomic::Ordering::SeqCst)
+}
+
+/// Returns whether SIGHUP has requested the config file to be reloaded since the last call.
+pub(crate) fn take_reload_request() -> bool {
+	RELOAD_REQUESTED.swap(false, std::sync::atomic::Ordering::SeqCst)
+}
+
+/// Sleeps for the given duration, or until a signal requests the process to exit or to reload the config file.
+pub(crate) fn sleep(duration: std::time::Duration) {
+	const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+	let deadline = std::time::Instant::now() + duration;
+	while !exit_requested() && !RELOAD_REQUESTED.load(std::sync::atomic::Ordering::SeqCst) {
+		let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+		if remaining == std::time::Duration::ZERO {
+			break;
+		}
+
+		std::thread::sleep(std::cmp::min(remaining, POLL_INTERVAL));
+	}
+}
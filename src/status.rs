@@ -0,0 +1,447 @@
+/// Prints a snapshot of the temperatures and the fan, as text or JSON.
+pub(crate) fn run(args: &crate::cli::Args, json: bool) -> Result<(), crate::Error> {
+	let hwmon = crate::acpi::Hwmon::find(&args.hwmon_root)?;
+	let state = crate::model::State::new(args, hwmon)?;
+	let status = Status::new(&state);
+
+	if json {
+		println!("{}", status.to_json());
+	}
+	else {
+		println!("{status}");
+	}
+
+	Ok(())
+}
+
+/// A snapshot of the state
+#[derive(Debug)]
+pub(crate) struct Status {
+	/// The sensors that have a name in the config
+	pub(crate) sensors: Result<Vec<SensorStatus>, String>,
+
+	/// The level and speed of each fan, starting from the first one
+	pub(crate) fans: Result<Vec<(crate::acpi::FanLevel, crate::acpi::FanSpeed)>, String>,
+	pub(crate) fan_is_writable: bool,
+
+	/// The indices of the sensors, starting from 1, that are above their critical temperature, which overrides the fan mode
+	pub(crate) failsafe: Vec<usize>,
+
+	/// The fans that did not take the level they were last set to
+	pub(crate) not_obeying: Vec<crate::model::FanNotObeying>,
+
+	/// Why the fans could not be controlled at the last update, if they couldn't
+	pub(crate) control_error: Option<String>,
+
+	/// The fan mode of the running instance, if this is its status
+	pub(crate) desired_fan_mode: Option<crate::model::DesiredFanMode>,
+
+	/// The manual fan level of the running instance, if this is its status
+	pub(crate) desired_manual_fan_level: Option<crate::model::DesiredManualFanLevel>,
+
+	/// Why the config file could not be reloaded, if it couldn't
+	pub(crate) config_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SensorStatus {
+	/// The index of the sensor, starting from 1
+	pub(crate) index: usize,
+	pub(crate) name: String,
+
+	/// The reading of the sensor
+	pub(crate) temp: Option<crate::acpi::Temp>,
+
+	/// The reading of the sensor after its filter is applied to it, ie what the controller uses
+	pub(crate) filtered_temp: Option<crate::acpi::Temp>,
+}
+
+impl Status {
+	pub(crate) fn new(state: &crate::model::State) -> Self {
+		let sensors = match &state.temps {
+			Ok(temps) =>
+				Ok(
+					state.config.sensors.iter().zip(temps).enumerate()
+					.filter_map(|(i, (name, temp))| name.as_ref().map(|name| SensorStatus {
+						index: i + 1,
+						name: name.clone(),
+						temp: *temp,
+						filtered_temp: state.filtered_temps.get(i).copied().flatten(),
+					}))
+					.collect()),
+
+			Err(err) => Err(format!("{err:?}")),
+		};
+
+		let fans = match &state.fans {
+			Ok(fans) => Ok(fans.clone()),
+			Err(err) => Err(format!("{err:?}")),
+		};
+
+		Status {
+			sensors,
+			fans,
+			fan_is_writable: state.fan_is_writable,
+
+			failsafe: state.failsafe.triggered().to_owned(),
+			not_obeying: state.not_obeying.clone(),
+			control_error: state.control_error.clone(),
+
+			desired_fan_mode: None,
+			desired_manual_fan_level: None,
+
+			config_error: state.config_error.clone(),
+		}
+	}
+
+	/// The status of a running instance, which also includes its fan mode and manual fan level.
+	pub(crate) fn of_running_instance(state: &crate::model::State) -> Self {
+		Status {
+			desired_fan_mode: Some(state.desired_fan_mode),
+			desired_manual_fan_level: Some(state.desired_manual_fan_level),
+			..Status::new(state)
+		}
+	}
+
+	pub(crate) fn to_json(&self) -> serde_json::Value {
+		let (sensors, sensors_error) = match &self.sensors {
+			Ok(sensors) => (
+				sensors.iter().map(|sensor| serde_json::json!({
+					"index": sensor.index,
+					"name": sensor.name,
+					"temp": sensor.temp.map(|temp| *temp.0),
+					"filtered_temp": sensor.filtered_temp.map(|temp| *temp.0),
+				})).collect(),
+				None,
+			),
+
+			Err(err) => (vec![], Some(err)),
+		};
+
+		// `fan.level` and `fan.speed` are those of the first fan. `fans` has all of them.
+		let (fans, fan_level, fan_speed, fan_error) = match &self.fans {
+			Ok(fans) => (
+				fans.iter().map(|(fan_level, fan_speed)| serde_json::json!({
+					"level": fan_level_name(*fan_level),
+					"speed": fan_speed.0,
+				})).collect(),
+				fans.first().map(|(fan_level, _)| fan_level_name(*fan_level)),
+				fans.first().map(|(_, fan_speed)| fan_speed.0),
+				None,
+			),
+			Err(err) => (vec![], None, None, Some(err)),
+		};
+
+		serde_json::json!({
+			"sensors": sensors,
+			"sensors_error": sensors_error,
+			"config_error": self.config_error,
+			"fans": fans,
+			"fan": {
+				"level": fan_level,
+				"speed": fan_speed,
+				"writable": self.fan_is_writable,
+				"error": fan_error,
+				"failsafe": self.failsafe,
+				"not_obeying": self.not_obeying.iter().map(|fan_not_obeying| serde_json::json!({
+					"fan": fan_not_obeying.fan,
+					"requested": fan_level_name(fan_not_obeying.requested),
+					"actual": fan_not_obeying.actual.as_ref().ok().map(|&actual| fan_level_name(actual)),
+					"error": fan_not_obeying.actual.as_ref().err(),
+				})).collect::<Vec<_>>(),
+				"control_error": self.control_error,
+				"mode": self.desired_fan_mode.map(fan_mode_name),
+				"manual_level": self.desired_manual_fan_level.map(|level| fan_level_name(level.into())),
+			},
+		})
+	}
+
+	/// Parses the output of [`Status::to_json`]. Returns `None` if it's malformed.
+	pub(crate) fn from_json(value: &serde_json::Value) -> Option<Self> {
+		let sensors = match value.get("sensors_error").and_then(serde_json::Value::as_str) {
+			Some(err) => Err(err.to_owned()),
+			None => Ok(
+				value.get("sensors")?.as_array()?.iter()
+				.map(|sensor| {
+					let temp = match sensor.get("temp")? {
+						serde_json::Value::Null => None,
+						temp => Some(crate::acpi::Temp(ordered_float::NotNan::new(temp.as_f64()?).ok()?)),
+					};
+					let filtered_temp = match sensor.get("filtered_temp") {
+						None => temp,
+						Some(serde_json::Value::Null) => None,
+						Some(filtered_temp) => Some(crate::acpi::Temp(ordered_float::NotNan::new(filtered_temp.as_f64()?).ok()?)),
+					};
+					Some(SensorStatus {
+						index: sensor.get("index")?.as_u64()?.try_into().ok()?,
+						name: sensor.get("name")?.as_str()?.to_owned(),
+						temp,
+						filtered_temp,
+					})
+				})
+				.collect::<Option<_>>()?),
+		};
+
+		let config_error = match value.get("config_error") {
+			None | Some(serde_json::Value::Null) => None,
+			Some(config_error) => Some(config_error.as_str()?.to_owned()),
+		};
+
+		let fan = value.get("fan")?;
+
+		let fan_is_writable = fan.get("writable")?.as_bool()?;
+
+		let failsafe = match fan.get("failsafe") {
+			None => vec![],
+			Some(failsafe) => failsafe.as_array()?.iter().map(|index| index.as_u64()?.try_into().ok()).collect::<Option<_>>()?,
+		};
+
+		let not_obeying = match fan.get("not_obeying") {
+			None => vec![],
+			Some(not_obeying) =>
+				not_obeying.as_array()?.iter()
+				.map(|fan_not_obeying| Some(crate::model::FanNotObeying {
+					fan: fan_not_obeying.get("fan")?.as_u64()?.try_into().ok()?,
+					requested: parse_fan_level_name(fan_not_obeying.get("requested")?.as_str()?)?,
+					actual: match fan_not_obeying.get("error").and_then(serde_json::Value::as_str) {
+						Some(err) => Err(err.to_owned()),
+						None => Ok(parse_fan_level_name(fan_not_obeying.get("actual")?.as_str()?)?),
+					},
+				}))
+				.collect::<Option<_>>()?,
+		};
+
+		let control_error = match fan.get("control_error") {
+			None | Some(serde_json::Value::Null) => None,
+			Some(control_error) => Some(control_error.as_str()?.to_owned()),
+		};
+
+		let desired_fan_mode = match fan.get("mode") {
+			None | Some(serde_json::Value::Null) => None,
+			Some(desired_fan_mode) => Some(desired_fan_mode.as_str()?.parse().ok()?),
+		};
+
+		let desired_manual_fan_level = match fan.get("manual_level") {
+			None | Some(serde_json::Value::Null) => None,
+			Some(desired_manual_fan_level) => Some(desired_manual_fan_level.as_str()?.parse().ok()?),
+		};
+
+		let fan_from_json = |fan: &serde_json::Value| {
+			let fan_level = parse_fan_level_name(fan.get("level")?.as_str()?)?;
+			let fan_speed = crate::acpi::FanSpeed(fan.get("speed")?.as_u64()?.try_into().ok()?);
+			Some((fan_level, fan_speed))
+		};
+
+		let fans =
+			if let Some(err) = fan.get("error").and_then(serde_json::Value::as_str) {
+				Err(err.to_owned())
+			}
+			else {
+				// Older versions only have the first fan.
+				match value.get("fans") {
+					None => Ok(vec![fan_from_json(fan)?]),
+					Some(fans) => Ok(fans.as_array()?.iter().map(fan_from_json).collect::<Option<_>>()?),
+				}
+			};
+
+		Some(Status {
+			sensors,
+			fans,
+			fan_is_writable,
+			failsafe,
+			not_obeying,
+			control_error,
+			desired_fan_mode,
+			desired_manual_fan_level,
+			config_error,
+		})
+	}
+}
+
+impl Status {
+	/// Describes which sensors are above their critical temperature, by name if they have one
+	pub(crate) fn failsafe_description(&self) -> String {
+		let sensors: Vec<_> =
+			self.failsafe.iter()
+			.map(|&index| {
+				let name = self.sensors.as_ref().ok().and_then(|sensors| sensors.iter().find(|sensor| sensor.index == index));
+				name.map_or_else(|| format!("sensor {index}"), |sensor| sensor.name.clone())
+			})
+			.collect();
+		format!("{} above critical temperature", sensors.join(", "))
+	}
+}
+
+impl std::fmt::Display for Status {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match &self.sensors {
+			Ok(sensors) => {
+				let width = sensors.iter().map(|sensor| sensor.name.len()).max().unwrap_or_default();
+				for sensor in sensors {
+					match (sensor.filtered_temp, sensor.temp) {
+						(Some(filtered_temp), Some(temp)) if filtered_temp != temp =>
+							writeln!(
+								f, "{:width$}  {} (raw {})",
+								sensor.name, filtered_temp.display(crate::acpi::TempScale::Celsius), temp.display(crate::acpi::TempScale::Celsius),
+							)?,
+						(Some(temp), _) | (None, Some(temp)) => writeln!(f, "{:width$}  {}", sensor.name, temp.display(crate::acpi::TempScale::Celsius))?,
+						(None, None) => writeln!(f, "{:width$}  n/a", sensor.name)?,
+					}
+				}
+			},
+
+			Err(err) => writeln!(f, "could not read temperatures: {err}")?,
+		}
+
+		writeln!(f)?;
+
+		match &self.fans {
+			Ok(fans) => {
+				let fan_levels: Vec<_> = fans.iter().map(|(fan_level, _)| fan_level.to_string()).collect();
+				let fan_speeds: Vec<_> = fans.iter().map(|(_, fan_speed)| fan_speed.to_string()).collect();
+				writeln!(f, "Fan level  {}", fan_levels.join(", "))?;
+				writeln!(f, "Fan speed  {}", fan_speeds.join(", "))?;
+			},
+
+			Err(err) => writeln!(f, "could not read fan: {err}")?,
+		}
+
+		write!(f, "Writable   {}", if self.fan_is_writable { "yes" } else { "no" })?;
+
+		if !self.failsafe.is_empty() {
+			write!(f, "\nFailsafe   {}", self.failsafe_description())?;
+		}
+
+		for fan_not_obeying in &self.not_obeying {
+			write!(f, "\nDisobeying {fan_not_obeying}")?;
+		}
+
+		if let Some(control_error) = &self.control_error {
+			write!(f, "\nError      could not control fan: {control_error}")?;
+		}
+
+		if let Some(desired_fan_mode) = self.desired_fan_mode {
+			write!(f, "\nMode       {desired_fan_mode}")?;
+		}
+
+		if let Some(desired_manual_fan_level) = self.desired_manual_fan_level {
+			write!(f, "\nManual     {desired_manual_fan_level}")?;
+		}
+
+		if let Some(config_error) = &self.config_error {
+			write!(f, "\n\ncould not reload config file: {config_error}")?;
+		}
+
+		Ok(())
+	}
+}
+
+/// The name of the fan mode, as it is given to the `set-mode` command of the control socket
+pub(crate) fn fan_mode_name(desired_fan_mode: crate::model::DesiredFanMode) -> &'static str {
+	match desired_fan_mode {
+		crate::model::DesiredFanMode::Bios => "bios",
+		crate::model::DesiredFanMode::Smart => "smart",
+		crate::model::DesiredFanMode::Target => "target",
+		crate::model::DesiredFanMode::Manual => "manual",
+	}
+}
+
+/// The name of the fan level, as it would be written in the `fan_level` table of the config
+pub(crate) fn fan_level_name(fan_level: crate::acpi::FanLevel) -> String {
+	match fan_level {
+		crate::acpi::FanLevel::Auto => "auto".to_owned(),
+		crate::acpi::FanLevel::Firmware(fan_firmware_level) => fan_firmware_level.to_string(),
+		crate::acpi::FanLevel::Disengaged => "disengaged".to_owned(),
+	}
+}
+
+/// Parses the output of [`fan_level_name`]
+fn parse_fan_level_name(name: &str) -> Option<crate::acpi::FanLevel> {
+	match name {
+		"auto" => Some(crate::acpi::FanLevel::Auto),
+		name => name.parse::<crate::model::DesiredManualFanLevel>().ok().map(Into::into),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	#[test]
+	fn to_json() {
+		let status = super::Status {
+			sensors: Ok(vec![
+				super::SensorStatus {
+					index: 1,
+					name: "cpu".to_owned(),
+					temp: Some(crate::acpi::Temp(ordered_float::NotNan::new(45.).unwrap())),
+					filtered_temp: Some(crate::acpi::Temp(ordered_float::NotNan::new(44.5).unwrap())),
+				},
+				super::SensorStatus {
+					index: 3,
+					name: "gpu".to_owned(),
+					temp: None,
+					filtered_temp: None,
+				},
+			]),
+			fans: Ok(vec![
+				(crate::acpi::FanLevel::Firmware(crate::acpi::FanFirmwareLevel::Three), crate::acpi::FanSpeed(2900)),
+				(crate::acpi::FanLevel::Firmware(crate::acpi::FanFirmwareLevel::Three), crate::acpi::FanSpeed(3100)),
+			]),
+			fan_is_writable: false,
+			failsafe: vec![3],
+			not_obeying: vec![crate::model::FanNotObeying {
+				fan: 1,
+				requested: crate::acpi::FanLevel::Firmware(crate::acpi::FanFirmwareLevel::Three),
+				actual: Ok(crate::acpi::FanLevel::Auto),
+			}],
+			control_error: Some("sysfs error: EIO".to_owned()),
+			desired_fan_mode: Some(crate::model::DesiredFanMode::Manual),
+			desired_manual_fan_level: Some(crate::model::DesiredManualFanLevel::Disengaged),
+			config_error: Some("invalid config".to_owned()),
+		};
+
+		assert_eq!(status.failsafe_description(), "gpu above critical temperature");
+
+		let json = status.to_json();
+
+		assert_eq!(json, serde_json::json!({
+			"sensors": [
+				{ "index": 1, "name": "cpu", "temp": 45., "filtered_temp": 44.5 },
+				{ "index": 3, "name": "gpu", "temp": null, "filtered_temp": null },
+			],
+			"sensors_error": null,
+			"config_error": "invalid config",
+			"fans": [{ "level": "3", "speed": 2900 }, { "level": "3", "speed": 3100 }],
+			"fan": { "level": "3", "speed": 2900, "writable": false, "error": null, "failsafe": [3],
+				"not_obeying": [{ "fan": 1, "requested": "3", "actual": "auto", "error": null }],
+				"control_error": "sysfs error: EIO",
+				"mode": "manual", "manual_level": "disengaged" },
+		}));
+
+		let status = super::Status::from_json(&json).unwrap();
+		assert_eq!(status.to_json(), json);
+
+		assert_eq!(status.to_string().lines().nth(4), Some("Fan speed  2900 RPM, 3100 RPM"));
+		assert_eq!(status.to_string().lines().nth(7), Some("Disobeying fan 1 was set to 3 but is at Auto"));
+		assert_eq!(status.to_string().lines().nth(8), Some("Error      could not control fan: sysfs error: EIO"));
+	}
+
+	#[test]
+	fn from_json_errors() {
+		let json = serde_json::json!({
+			"sensors": [],
+			"sensors_error": "sysfs error: ENXIO",
+			"config_error": null,
+			"fans": [],
+			"fan": { "level": null, "speed": null, "writable": true, "error": "sysfs error: ENXIO", "failsafe": [], "not_obeying": [], "control_error": null, "mode": null, "manual_level": null },
+		});
+
+		let status = super::Status::from_json(&json).unwrap();
+		assert_eq!(status.sensors.as_ref().unwrap_err(), "sysfs error: ENXIO");
+		assert_eq!(status.fans.as_ref().unwrap_err(), "sysfs error: ENXIO");
+		assert!(status.fan_is_writable);
+		assert_eq!(status.desired_fan_mode, None);
+		assert_eq!(status.to_json(), json);
+
+		assert!(super::Status::from_json(&serde_json::json!({ "sensors": [] })).is_none());
+	}
+}
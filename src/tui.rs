@@ -0,0 +1,519 @@
+use crate::{acpi, model, status::Status, Error};
+
+pub(crate) fn run(args: &crate::cli::Args) -> Result<(), Error> {
+	// Declared before the window so that it's dropped after it, once the terminal has been restored.
+	let _log_buffer = crate::logger::buffer();
+
+	let (mut source, mut status) = Source::new(args)?;
+
+	let mut ui_state = UiState {
+		visible_temp_sensors: Default::default(),
+		temp_scale: Default::default(),
+		desired_fan_mode: status.desired_fan_mode.unwrap_or(model::DesiredFanMode::Bios),
+		desired_manual_fan_level: status.desired_manual_fan_level.unwrap_or_default(),
+	};
+
+	let history_len = usize::try_from(args.history.as_secs() / HISTORY_INTERVAL.as_secs()).unwrap_or(usize::MAX).max(1);
+	let mut history = crate::history::History::new(history_len);
+	history.push(&status);
+	let mut next_history_push = std::time::Instant::now() + HISTORY_INTERVAL;
+
+	let mut window = cursive::Cursive::new();
+	window.set_fps(2);
+
+	window.add_fullscreen_layer(render(&ui_state, &status));
+
+	// The controls are enabled according to whether the fan is writable when they're rendered, so they're rendered again when it changes.
+	let mut rendered_fan_is_writable = status.fan_is_writable;
+
+	let reload_requested: std::rc::Rc<std::cell::Cell<bool>> = Default::default();
+	window.add_global_callback(RELOAD_KEY, {
+		let reload_requested = reload_requested.clone();
+		move |_| reload_requested.set(true)
+	});
+
+	let mut window = window.runner(cursive::backends::termion::Backend::init().map_err(Error::InitializeUi)?);
+
+	loop {
+		match source.update() {
+			Ok(Some(new_status)) => status = new_status,
+			Ok(None) => (),
+			Err(err) => {
+				window.quit();
+				return Err(err);
+			},
+		}
+
+		let now = std::time::Instant::now();
+		if now >= next_history_push {
+			history.push(&status);
+			next_history_push = now + HISTORY_INTERVAL;
+		}
+
+		if reload_requested.replace(false) {
+			if let Err(err) = source.reload_config(args) {
+				window.quit();
+				return Err(err);
+			}
+		}
+
+		ui_state.visible_temp_sensors =
+			window
+			.call_on_name(VISIBLE_TEMP_SENSORS_GROUP_ID,
+				|visible_temp_sensors_group: &mut RadioGroupView<model::VisibleTempSensors>| visible_temp_sensors_group.0.selection())
+			.map_or_else(Default::default, |visible_temp_sensors| *visible_temp_sensors);
+
+		ui_state.temp_scale =
+			window
+			.call_on_name(TEMP_SCALE_GROUP_ID,
+				|temp_scale_group: &mut RadioGroupView<acpi::TempScale>| temp_scale_group.0.selection())
+			.map_or_else(Default::default, |temp_scale| *temp_scale);
+
+		let desired_fan_mode =
+			window
+			.call_on_name(FAN_SPEED_GROUP_ID,
+				|fan_speed_group: &mut RadioGroupView<model::DesiredFanMode>| fan_speed_group.0.selection())
+			.map_or(ui_state.desired_fan_mode, |desired_fan_mode| *desired_fan_mode);
+
+		let desired_manual_fan_level =
+			window
+			.call_on_name(DESIRED_MANUAL_FAN_LEVEL_ID,
+				|desired_manual_fan_level: &mut cursive::views::SelectView<model::DesiredManualFanLevel>| desired_manual_fan_level.selection())
+			.and_then(|desired_manual_fan_level| desired_manual_fan_level)
+			.map_or(ui_state.desired_manual_fan_level, |desired_manual_fan_level| *desired_manual_fan_level);
+
+		if let Source::Daemon(connection) = &mut source {
+			// Changes made in the UI are sent to the daemon. Otherwise, changes made by other clients of the daemon are shown in the UI.
+			let result =
+				if desired_fan_mode != ui_state.desired_fan_mode {
+					ui_state.desired_fan_mode = desired_fan_mode;
+					connection.set_mode(desired_fan_mode)
+				}
+				else if desired_manual_fan_level != ui_state.desired_manual_fan_level {
+					ui_state.desired_manual_fan_level = desired_manual_fan_level;
+					connection.set_level(desired_manual_fan_level)
+				}
+				else {
+					if let (false, Some(desired_fan_mode), Some(desired_manual_fan_level)) =
+						(connection.has_pending_requests(), status.desired_fan_mode, status.desired_manual_fan_level)
+					{
+						if desired_fan_mode != ui_state.desired_fan_mode || desired_manual_fan_level != ui_state.desired_manual_fan_level {
+							ui_state.desired_fan_mode = desired_fan_mode;
+							ui_state.desired_manual_fan_level = desired_manual_fan_level;
+							window.pop_layer();
+							window.add_fullscreen_layer(render(&ui_state, &status));
+						}
+					}
+
+					Ok(())
+				};
+
+			if let Err(err) = result {
+				window.quit();
+				return Err(err);
+			}
+		}
+
+		if status.fan_is_writable != rendered_fan_is_writable {
+			rendered_fan_is_writable = status.fan_is_writable;
+			window.pop_layer();
+			window.add_fullscreen_layer(render(&ui_state, &status));
+		}
+
+		let temps_view_contents = render_temps(&ui_state, &status, &history);
+		window.call_on_name(TEMPS_VIEW_ID, |temps_view: &mut cursive::views::StackView| {
+			temps_view.pop_layer();
+			temps_view.add_fullscreen_layer(temps_view_contents);
+		}).unwrap();
+
+		let fan_view_contents = render_fan(&status, &history, source.read_only_reason(), source.rejection());
+		window.call_on_name(FAN_VIEW_ID, |fan_view: &mut cursive::views::StackView| {
+			fan_view.pop_layer();
+			fan_view.add_fullscreen_layer(fan_view_contents);
+		}).unwrap();
+
+		window.step();
+
+		if !window.is_running() {
+			return Ok(());
+		}
+
+		if crate::signal::exit_requested() {
+			log::info!("exiting on signal");
+			window.quit();
+			return Ok(());
+		}
+	}
+}
+
+/// Where the UI gets the temperatures and the state of the fan from
+enum Source {
+	/// The daemon, through its control socket. The UI controls the fan through the daemon, if the daemon allows it.
+	Daemon(crate::control::Connection),
+
+	/// The hwmon device, when the daemon is not running. The UI only monitors the fan in this case, and never writes to it.
+	///
+	/// The sensors are read once per update interval, like the daemon does, so that the filters in the config see the same readings.
+	Local { state: model::State, next_update: std::time::Instant },
+}
+
+impl Source {
+	fn new(args: &crate::cli::Args) -> Result<(Self, Status), Error> {
+		match crate::control::Connection::subscribe(&args.socket) {
+			Ok((connection, status)) => Ok((Source::Daemon(connection), status)),
+
+			Err(err) => {
+				log::info!("the fan cannot be controlled because the daemon is not available: {err:?}");
+
+				let hwmon = acpi::Hwmon::find(&args.hwmon_root)?;
+				let state = model::State::new(args, hwmon)?;
+				let status = Source::local_status(&state);
+				let next_update = std::time::Instant::now() + state.config.update_interval;
+				Ok((Source::Local { state, next_update }, status))
+			},
+		}
+	}
+
+	/// Returns the new status, if any.
+	fn update(&mut self) -> Result<Option<Status>, Error> {
+		match self {
+			Source::Daemon(connection) => connection.poll(),
+
+			Source::Local { state, next_update } => {
+				let now = std::time::Instant::now();
+				if now < *next_update {
+					return Ok(None);
+				}
+
+				state.update_sensors();
+				*next_update = now + state.config.update_interval;
+				Ok(Some(Source::local_status(state)))
+			},
+		}
+	}
+
+	fn local_status(state: &model::State) -> Status {
+		Status {
+			fan_is_writable: false,
+			..Status::new(state)
+		}
+	}
+
+	/// Reloads the config file, in the daemon if the UI is connected to it.
+	///
+	/// If the new config file is invalid, the error shows up in the status.
+	fn reload_config(&mut self, args: &crate::cli::Args) -> Result<(), Error> {
+		match self {
+			Source::Daemon(connection) => connection.reload(),
+
+			Source::Local { state, .. } => {
+				match state.reload_config(args) {
+					Ok(()) => log::info!("reloaded config file {}", state.config_path.display()),
+					Err(err) => log::warn!("could not reload config file, keeping the previous config: {err:?}"),
+				}
+				Ok(())
+			},
+		}
+	}
+
+	/// Why the daemon rejected the last request of the UI, if it did
+	fn rejection(&self) -> Option<&str> {
+		match self {
+			Source::Daemon(connection) => connection.rejection(),
+			Source::Local { .. } => None,
+		}
+	}
+
+	/// Why the fan cannot be controlled, when the status says it isn't writable
+	fn read_only_reason(&self) -> &'static str {
+		match self {
+			Source::Daemon(_) => "Read-only; the daemon does not allow this user to control the fan",
+			Source::Local { .. } => "Read-only; the daemon is not running",
+		}
+	}
+}
+
+/// The selections of the UI
+#[derive(Clone, Copy, Debug)]
+struct UiState {
+	visible_temp_sensors: model::VisibleTempSensors,
+	temp_scale: acpi::TempScale,
+	desired_fan_mode: model::DesiredFanMode,
+	desired_manual_fan_level: model::DesiredManualFanLevel,
+}
+
+/// How often the latest readings are added to the history, so that it covers the same time
+/// whether the UI gets them from the daemon or reads them itself
+const HISTORY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The key that reloads the config file
+const RELOAD_KEY: char = 'r';
+
+const TEMPS_VIEW_ID: &str = "temps_view";
+const FAN_VIEW_ID: &str = "fan_view";
+const VISIBLE_TEMP_SENSORS_GROUP_ID: &str = "visible_temp_sensors_group";
+const TEMP_SCALE_GROUP_ID: &str = "temp_scale_group";
+const FAN_SPEED_GROUP_ID: &str = "fan_speed_group";
+const DESIRED_MANUAL_FAN_LEVEL_ID: &str = "desired_manual_fan_level";
+
+fn render(ui_state: &UiState, status: &Status) -> cursive::views::LinearLayout {
+	use cursive::view::Resizable;
+
+	cursive::views::LinearLayout::horizontal()
+	.child(
+		cursive::views::Panel::new(
+			cursive::views::LinearLayout::vertical()
+			.child(cursive::views::TextView::new("Temperatures").center().full_width())
+			.child(cursive::views::NamedView::new(TEMPS_VIEW_ID, cursive::views::StackView::new()))
+			.child({
+				let mut visible_temp_sensors_group = cursive::views::RadioGroup::new();
+
+				cursive::views::LinearLayout::horizontal()
+				.child({
+					let mut button = visible_temp_sensors_group.button(model::VisibleTempSensors::All, model::VisibleTempSensors::All.to_string());
+					if let model::VisibleTempSensors::All = ui_state.visible_temp_sensors {
+						button.select();
+					}
+					button.full_width()
+				})
+				.child({
+					let mut button = visible_temp_sensors_group.button(model::VisibleTempSensors::Active, model::VisibleTempSensors::Active.to_string());
+					if let model::VisibleTempSensors::Active = ui_state.visible_temp_sensors {
+						button.select();
+					}
+					button.full_width()
+				})
+				.child(cursive::views::NamedView::new(VISIBLE_TEMP_SENSORS_GROUP_ID, RadioGroupView(visible_temp_sensors_group)))
+			})
+			.child({
+				let mut temp_scale_group = cursive::views::RadioGroup::new();
+
+				cursive::views::LinearLayout::horizontal()
+				.child({
+					let mut button = temp_scale_group.button(acpi::TempScale::Celsius, acpi::TempScale::Celsius.to_string());
+					if let acpi::TempScale::Celsius = ui_state.temp_scale {
+						button.select();
+					}
+					button.full_width()
+				})
+				.child({
+					let mut button = temp_scale_group.button(acpi::TempScale::Fahrenheit, acpi::TempScale::Fahrenheit.to_string());
+					if let acpi::TempScale::Fahrenheit = ui_state.temp_scale {
+						button.select();
+					}
+					button.full_width()
+				})
+				.child(cursive::views::NamedView::new(TEMP_SCALE_GROUP_ID, RadioGroupView(temp_scale_group)))
+			})))
+	.child(
+		cursive::views::Panel::new(
+			cursive::views::LinearLayout::vertical()
+			.child(cursive::views::TextView::new("Fan").center().full_width())
+			.child(cursive::views::NamedView::new(FAN_VIEW_ID, cursive::views::StackView::new()))
+			.child(
+				cursive::views::LinearLayout::horizontal()
+				.child(cursive::views::TextView::new("Mode").full_width())
+				.child({
+					let mut fan_speed_group = cursive::views::RadioGroup::new();
+
+					cursive::views::LinearLayout::vertical()
+					.child({
+						let mut button = fan_speed_group.button(model::DesiredFanMode::Bios, model::DesiredFanMode::Bios.to_string());
+						if let model::DesiredFanMode::Bios = ui_state.desired_fan_mode {
+							button.select();
+						}
+						button.set_enabled(status.fan_is_writable);
+						button
+					})
+					.child({
+						let mut button = fan_speed_group.button(model::DesiredFanMode::Smart, model::DesiredFanMode::Smart.to_string());
+						if let model::DesiredFanMode::Smart = ui_state.desired_fan_mode {
+							button.select();
+						}
+						button.set_enabled(status.fan_is_writable);
+						button
+					})
+					.child({
+						let mut button = fan_speed_group.button(model::DesiredFanMode::Target, model::DesiredFanMode::Target.to_string());
+						if let model::DesiredFanMode::Target = ui_state.desired_fan_mode {
+							button.select();
+						}
+						button.set_enabled(status.fan_is_writable);
+						button
+					})
+					.child(
+						cursive::views::LinearLayout::horizontal()
+						.child({
+							let mut button = fan_speed_group.button(model::DesiredFanMode::Manual, model::DesiredFanMode::Manual.to_string());
+							if let model::DesiredFanMode::Manual = ui_state.desired_fan_mode {
+								button.select();
+							}
+							button.set_enabled(status.fan_is_writable);
+							button
+						})
+						.child({
+							let all_desired_manual_fan_levels = [
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Zero),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::One),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Two),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Three),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Four),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Five),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Six),
+								model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Seven),
+								model::DesiredManualFanLevel::Disengaged,
+							];
+
+							let mut view =
+								cursive::views::SelectView::new()
+								.popup()
+								.with_all(all_desired_manual_fan_levels.iter().map(|&desired_manual_fan_level|
+									(desired_manual_fan_level_label(desired_manual_fan_level), desired_manual_fan_level)));
+							view.set_selection(all_desired_manual_fan_levels.iter().position(|v| v == &ui_state.desired_manual_fan_level).unwrap());
+							view.set_enabled(status.fan_is_writable);
+							cursive::views::NamedView::new(DESIRED_MANUAL_FAN_LEVEL_ID, view)
+						}))
+					.child(cursive::views::NamedView::new(FAN_SPEED_GROUP_ID, RadioGroupView(fan_speed_group)))
+				}))))
+}
+
+/// The label of the level in the manual fan level selector, which points out the difference between level 7 and disengaged
+fn desired_manual_fan_level_label(desired_manual_fan_level: model::DesiredManualFanLevel) -> String {
+	match desired_manual_fan_level {
+		model::DesiredManualFanLevel::Firmware(acpi::FanFirmwareLevel::Seven) => format!("{desired_manual_fan_level} (fastest regulated)"),
+		model::DesiredManualFanLevel::Firmware(_) => desired_manual_fan_level.to_string(),
+		model::DesiredManualFanLevel::Disengaged => format!("{desired_manual_fan_level} (unregulated)"),
+	}
+}
+
+fn render_temps(ui_state: &UiState, status: &Status, history: &crate::history::History) -> cursive::views::LinearLayout {
+	use cursive::view::Resizable;
+
+	match &status.sensors {
+		Ok(sensors) =>
+			cursive::views::LinearLayout::vertical()
+			.child(
+				sensors.iter().fold(
+					cursive::views::ListView::new(),
+					|layout, sensor| match (sensor.filtered_temp.or(sensor.temp), ui_state.visible_temp_sensors) {
+						(Some(filtered_temp), _) => {
+							let text = match sensor.temp {
+								Some(temp) if temp != filtered_temp => format!("{} (raw {})", filtered_temp.display(ui_state.temp_scale), temp.display(ui_state.temp_scale)),
+								_ => filtered_temp.display(ui_state.temp_scale).to_string(),
+							};
+							let text = format!("{}  {text}", history.temp_sparkline(sensor.index));
+							layout
+							.child(&sensor.name, cursive::views::TextView::new(text).h_align(cursive::align::HAlign::Right).full_width())
+						},
+						(None, model::VisibleTempSensors::All) =>
+							layout
+							.child(&sensor.name, cursive::views::TextView::new("n/a").h_align(cursive::align::HAlign::Right).full_width()),
+						(None, model::VisibleTempSensors::Active) =>
+							layout,
+					},
+				)
+				.full_screen()),
+
+		Err(err) =>
+			cursive::views::LinearLayout::vertical()
+			.child(cursive::views::TextView::new(format!("Could not read temperatures: {err}")).full_screen()),
+	}
+}
+
+fn render_fan(status: &Status, history: &crate::history::History, read_only_reason: &str, rejection: Option<&str>) -> cursive::views::LinearLayout {
+	use cursive::view::Resizable;
+
+	let layout = match &status.fans {
+		Ok(fans) =>
+			fans.iter().enumerate()
+			.fold(cursive::views::LinearLayout::vertical(), |layout, (i, (fan_level, fan_speed))| {
+				let index = i + 1;
+				let (level_label, speed_label) =
+					if fans.len() == 1 {
+						("Level".to_owned(), "Speed".to_owned())
+					}
+					else {
+						(format!("Fan {index} level"), format!("Fan {index} speed"))
+					};
+
+				layout
+				.child(
+					cursive::views::LinearLayout::horizontal()
+					.child(cursive::views::TextView::new(level_label).full_width())
+					.child(cursive::views::TextView::new(fan_level.to_string()))
+					.full_height())
+				.child(
+					cursive::views::LinearLayout::horizontal()
+					.child(cursive::views::TextView::new(speed_label).full_width())
+					.child(cursive::views::TextView::new(format!("{}  {fan_speed}", history.fan_speed_sparkline(index))))
+					.full_height())
+			}),
+
+		Err(err) =>
+			cursive::views::LinearLayout::vertical()
+			.child(cursive::views::TextView::new(format!("Could not read fan: {err}")).full_height()),
+	};
+
+	let layout =
+		if status.failsafe.is_empty() {
+			layout
+		}
+		else {
+			layout.child(cursive::views::TextView::new(cursive::utils::markup::StyledString::styled(
+				format!("FAILSAFE: {}", status.failsafe_description()),
+				cursive::theme::Color::Light(cursive::theme::BaseColor::Red),
+			)))
+		};
+
+	let layout =
+		status.not_obeying.iter()
+		.fold(layout, |layout, fan_not_obeying|
+			layout.child(cursive::views::TextView::new(cursive::utils::markup::StyledString::styled(
+				format!("NOT OBEYING: {fan_not_obeying}"),
+				cursive::theme::Color::Light(cursive::theme::BaseColor::Red),
+			))));
+
+	let layout = match &status.control_error {
+		Some(control_error) =>
+			layout.child(cursive::views::TextView::new(cursive::utils::markup::StyledString::styled(
+				format!("Could not control fan: {control_error}"),
+				cursive::theme::Color::Light(cursive::theme::BaseColor::Red),
+			))),
+
+		None => layout,
+	};
+
+	let layout = match &status.config_error {
+		Some(config_error) =>
+			layout.child(cursive::views::TextView::new(cursive::utils::markup::StyledString::styled(
+				format!("Could not reload config file: {config_error}"),
+				cursive::theme::Color::Light(cursive::theme::BaseColor::Red),
+			))),
+
+		None => layout,
+	};
+
+	let layout = match rejection {
+		Some(rejection) =>
+			layout.child(cursive::views::TextView::new(cursive::utils::markup::StyledString::styled(
+				format!("Daemon rejected the change: {rejection}"),
+				cursive::theme::Color::Light(cursive::theme::BaseColor::Red),
+			))),
+
+		None => layout,
+	};
+
+	if status.fan_is_writable {
+		layout
+	}
+	else {
+		layout.child(cursive::views::TextView::new(read_only_reason))
+	}
+}
+
+struct RadioGroupView<T>(cursive::views::RadioGroup<T>);
+
+impl<T> cursive::view::View for RadioGroupView<T> where T: 'static {
+	fn draw(&self, _: &cursive::Printer<'_, '_>) {
+	}
+}
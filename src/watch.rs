@@ -0,0 +1,104 @@
+/// Watches a file for changes with inotify.
+///
+/// The directory of the file is watched rather than the file itself, so that changes are also noticed
+/// when an editor replaces the file with a new one instead of writing to it.
+#[derive(Debug)]
+pub(crate) struct FileWatcher {
+	inotify: std::fs::File,
+	file_name: std::ffi::OsString,
+}
+
+impl FileWatcher {
+	pub(crate) fn new(path: &std::path::Path) -> std::io::Result<Self> {
+		let dir = match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => dir,
+			_ => std::path::Path::new("."),
+		};
+		let file_name = path.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path does not have a file name"))?;
+
+		let inotify = unsafe {
+			let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+			if fd == -1 {
+				return Err(std::io::Error::last_os_error());
+			}
+			<std::fs::File as std::os::unix::io::FromRawFd>::from_raw_fd(fd)
+		};
+
+		let dir = std::ffi::CString::new(std::os::unix::ffi::OsStrExt::as_bytes(dir.as_os_str()))
+			.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+		let wd = unsafe {
+			libc::inotify_add_watch(
+				std::os::unix::io::AsRawFd::as_raw_fd(&inotify),
+				dir.as_ptr(),
+				libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+			)
+		};
+		if wd == -1 {
+			return Err(std::io::Error::last_os_error());
+		}
+
+		Ok(FileWatcher {
+			inotify,
+			file_name: file_name.to_owned(),
+		})
+	}
+
+	/// Returns whether the file has changed since the last call, without blocking.
+	pub(crate) fn poll(&mut self) -> std::io::Result<bool> {
+		const EVENT_HEADER_LEN: usize = std::mem::size_of::<libc::inotify_event>();
+
+		let mut result = false;
+
+		let mut buf = [0_u8; 4096];
+		loop {
+			let len = match std::io::Read::read(&mut self.inotify, &mut buf) {
+				Ok(len) => len,
+				Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(result),
+				Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+				Err(err) => return Err(err),
+			};
+
+			let mut events = &buf[..len];
+			while events.len() >= EVENT_HEADER_LEN {
+				let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(events.as_ptr().cast()) };
+				let name_len = event.len as usize;
+				let name = events.get(EVENT_HEADER_LEN..(EVENT_HEADER_LEN + name_len)).unwrap_or_default();
+
+				// The name is padded with NULs.
+				let name = name.split(|&b| b == b'\0').next().unwrap_or_default();
+				if name == std::os::unix::ffi::OsStrExt::as_bytes(&*self.file_name) {
+					result = true;
+				}
+
+				events = events.get((EVENT_HEADER_LEN + name_len)..).unwrap_or_default();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	#[test]
+	fn poll() {
+		let dir = std::env::temp_dir().join(format!("tpfancontrol-test-{}-watch", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("config.toml");
+		std::fs::write(&path, "a").unwrap();
+
+		let mut watcher = super::FileWatcher::new(&path).unwrap();
+		assert!(!watcher.poll().unwrap());
+
+		std::fs::write(dir.join("other.toml"), "a").unwrap();
+		assert!(!watcher.poll().unwrap());
+
+		std::fs::write(&path, "b").unwrap();
+		assert!(watcher.poll().unwrap());
+		assert!(!watcher.poll().unwrap());
+
+		// Replaced by renaming another file over it
+		std::fs::rename(dir.join("other.toml"), &path).unwrap();
+		assert!(watcher.poll().unwrap());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}